@@ -0,0 +1,29 @@
+//! Decodes arbitrary data as received by a client, and checks that every decoded frame is
+//! encoded back into the same frame.
+
+#![no_main]
+
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+use tokio_util::codec::{Decoder, Encoder};
+
+use tomsg_rs::codec::{ClientCodec, ClientFrame, ServerCodec};
+
+fuzz_target!(|data: &[u8]| {
+    let mut codec = ClientCodec::new();
+    let mut buf = BytesMut::from(data);
+
+    while let Ok(Some(frame)) = codec.decode(&mut buf) {
+        let mut encoded = BytesMut::new();
+        match &frame {
+            ClientFrame::Reply { tag, reply } => {
+                ServerCodec::new().encode((&**tag, reply), &mut encoded).unwrap()
+            }
+            ClientFrame::Push(push) => ServerCodec::new().encode(push, &mut encoded).unwrap(),
+            ClientFrame::History { .. } | ClientFrame::HistoryMessage { .. } => continue,
+        }
+
+        let decoded = ClientCodec::new().decode(&mut encoded).unwrap().unwrap();
+        assert_eq!(format!("{:?}", decoded), format!("{:?}", frame));
+    }
+});
@@ -0,0 +1,31 @@
+//! Decodes arbitrary data as received by a server, and checks that every decoded command is
+//! encoded back into the same command.
+
+#![no_main]
+
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+use tokio_util::codec::{Decoder, Encoder};
+
+use tomsg_rs::codec::{ClientCodec, ServerCodec};
+
+fuzz_target!(|data: &[u8]| {
+    let mut codec = ServerCodec::new();
+    let mut buf = BytesMut::from(data);
+
+    while let Ok(Some(frame)) = codec.decode(&mut buf) {
+        let command = match &frame.command {
+            Ok(c) => c,
+            Err(_) => continue,
+        };
+
+        let mut encoded = BytesMut::new();
+        ClientCodec::new()
+            .encode((&*frame.tag, command), &mut encoded)
+            .unwrap();
+
+        let decoded = ServerCodec::new().decode(&mut encoded).unwrap().unwrap();
+        assert_eq!(decoded.tag, frame.tag);
+        assert_eq!(decoded.command.as_ref(), Ok(command));
+    }
+});
@@ -0,0 +1,84 @@
+//! A reference tomsg server, which keeps all of its state in memory.
+//!
+//! Usage: `tomsg-server [address]`, where `address` defaults to `127.0.0.1:29536`.
+
+mod state;
+
+use std::convert::TryFrom;
+use std::env;
+use std::io;
+use std::process;
+use std::sync::{Arc, Mutex};
+
+use futures_util::StreamExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_util::codec::FramedRead;
+
+use tomsg_rs::codec::ServerCodec;
+use tomsg_rs::{Line, Reply};
+
+use crate::state::{encoded_line, State};
+
+/// The maximum length of a command line, excluding the newline.
+///
+/// A client sending a longer line is disconnected, so that it can't make the server buffer an
+/// unbounded amount of data.
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+async fn serve(state: Arc<Mutex<State>>, stream: TcpStream) {
+    let (reader, mut writer) = stream.into_split();
+
+    let (sender, mut receiver) = mpsc::unbounded_channel::<String>();
+    let session = state.lock().unwrap().connect(sender.clone());
+
+    let write_task = tokio::spawn(async move {
+        while let Some(line) = receiver.recv().await {
+            if writer.write_all(line.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut frames = FramedRead::new(reader, ServerCodec::new_with_max_length(MAX_LINE_LENGTH));
+    while let Some(Ok(frame)) = frames.next().await {
+        let reply = match &frame.command {
+            Ok(command) => state.lock().unwrap().handle(session, command),
+            Err(e) => Reply::Error(Box::<Line>::try_from(e.clone()).unwrap()),
+        };
+        if sender.send(encoded_line(reply.encode(&frame.tag))).is_err() {
+            break;
+        }
+    }
+
+    state.lock().unwrap().disconnect(session);
+    drop(sender);
+    let _ = write_task.await;
+}
+
+async fn run(address: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(address).await?;
+    eprintln!("listening on {}", listener.local_addr()?);
+
+    let state = Arc::new(Mutex::new(State::new()));
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(serve(state.clone(), stream));
+    }
+}
+
+fn main() {
+    let address = env::args()
+        .nth(1)
+        .unwrap_or_else(|| String::from("127.0.0.1:29536"));
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to create runtime");
+    if let Err(e) = runtime.block_on(run(&address)) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
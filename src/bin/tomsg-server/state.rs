@@ -0,0 +1,387 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::convert::{TryFrom, TryInto};
+use std::time::SystemTime;
+
+use tokio::sync::mpsc;
+
+use tomsg_rs::{Command, Id, Line, Message, PushMessage, Reply, RoomName, Username};
+
+pub type SessionId = u64;
+
+fn error(message: &str) -> Reply {
+    Reply::Error(Box::<Line>::try_from(message.to_owned()).unwrap())
+}
+
+/// Turns an encoded reply or push message into a line, including the trailing newline.
+pub fn encoded_line(encoded: Result<String, &'static str>) -> String {
+    // every message is created by the server with a valid timestamp, so encoding can't fail.
+    format!("{}\n", encoded.unwrap())
+}
+
+struct Session {
+    username: Option<Box<Username>>,
+    sender: mpsc::UnboundedSender<String>,
+}
+
+struct User {
+    password: Box<Line>,
+    rooms: BTreeSet<Box<RoomName>>,
+    sessions: HashSet<SessionId>,
+}
+
+#[derive(Default)]
+struct Room {
+    members: BTreeSet<Box<Username>>,
+    messages: Vec<Id>,
+}
+
+/// The state of the server: all users, rooms, messages and sessions.
+pub struct State {
+    users: HashMap<Box<Username>, User>,
+    rooms: HashMap<Box<RoomName>, Room>,
+    /// All messages, the message with ID `i` is at index `i`.
+    messages: Vec<Message>,
+    sessions: HashMap<SessionId, Session>,
+    next_session: SessionId,
+    next_room: u64,
+}
+
+impl State {
+    pub fn new() -> Self {
+        Self {
+            users: HashMap::new(),
+            rooms: HashMap::new(),
+            messages: Vec::new(),
+            sessions: HashMap::new(),
+            next_session: 0,
+            next_room: 0,
+        }
+    }
+
+    /// Registers a new session, to which push messages are sent using `sender`.
+    pub fn connect(&mut self, sender: mpsc::UnboundedSender<String>) -> SessionId {
+        let session = self.next_session;
+        self.next_session += 1;
+        self.sessions.insert(
+            session,
+            Session {
+                username: None,
+                sender,
+            },
+        );
+        session
+    }
+
+    /// Removes the given `session`, logging it out if needed.
+    pub fn disconnect(&mut self, session: SessionId) {
+        self.logout(session);
+        self.sessions.remove(&session);
+    }
+
+    fn push_to_user(&self, username: &Username, push: &PushMessage, except: Option<SessionId>) {
+        let user = match self.users.get(username) {
+            Some(u) => u,
+            None => return,
+        };
+        let line = encoded_line(push.encode());
+        for session in &user.sessions {
+            if Some(*session) == except {
+                continue;
+            }
+            if let Some(s) = self.sessions.get(session) {
+                let _ = s.sender.send(line.clone());
+            }
+        }
+    }
+
+    fn push_to_room(&self, roomname: &RoomName, push: &PushMessage, except: Option<SessionId>) {
+        if let Some(room) = self.rooms.get(roomname) {
+            for member in &room.members {
+                self.push_to_user(member, push, except);
+            }
+        }
+    }
+
+    /// Notifies everyone sharing a room with `username` of its amount of online sessions.
+    fn push_online(&self, username: &Username, except: SessionId) {
+        let user = &self.users[username];
+        let push = PushMessage::Online {
+            sessions: user.sessions.len() as i64,
+            username: username.to_owned(),
+        };
+
+        let mut others: BTreeSet<&Username> = BTreeSet::new();
+        for roomname in &user.rooms {
+            others.extend(self.rooms[roomname].members.iter().map(|m| &**m));
+        }
+        for other in others {
+            self.push_to_user(other, &push, Some(except));
+        }
+    }
+
+    fn logout(&mut self, session: SessionId) {
+        let username = match self.sessions.get_mut(&session) {
+            Some(s) => s.username.take(),
+            None => None,
+        };
+        if let Some(username) = username {
+            self.users
+                .get_mut(&username)
+                .unwrap()
+                .sessions
+                .remove(&session);
+            self.push_online(&username, session);
+        }
+    }
+
+    /// Handles the given `command` sent by `session`, returning the reply.
+    pub fn handle(&mut self, session: SessionId, command: &Command<'_>) -> Reply {
+        match command {
+            Command::Version(version) if version.as_str() == "4" => return Reply::Ok,
+            Command::Version(_) => return error("Version not supported"),
+            Command::Ping => return Reply::Pong,
+            _ => {}
+        }
+
+        let username = self.sessions[&session].username.clone();
+        match (command, username) {
+            (Command::Register { .. }, Some(_)) | (Command::Login { .. }, Some(_)) => {
+                error("Already logged in")
+            }
+            (Command::Register { username, password }, None) => {
+                if self.users.contains_key(&**username) {
+                    return error("Username already exists");
+                }
+                self.users.insert(
+                    username.clone().into_owned(),
+                    User {
+                        password: password.clone().into_owned(),
+                        rooms: BTreeSet::new(),
+                        sessions: HashSet::new(),
+                    },
+                );
+                Reply::Ok
+            }
+            (Command::Login { username, password }, None) => {
+                let user = match self.users.get_mut(&**username) {
+                    None => return error("User not found"),
+                    Some(u) => u,
+                };
+                if *user.password != **password {
+                    return error("Incorrect password");
+                }
+                user.sessions.insert(session);
+                self.sessions.get_mut(&session).unwrap().username =
+                    Some(username.clone().into_owned());
+                self.push_online(username, session);
+                Reply::Ok
+            }
+
+            (_, None) => error("Not logged in"),
+
+            (Command::ChangePassword(password), Some(username)) => {
+                self.users.get_mut(&username).unwrap().password = password.clone().into_owned();
+                Reply::Ok
+            }
+            (Command::Logout, Some(_)) => {
+                self.logout(session);
+                Reply::Ok
+            }
+            (Command::ListRooms, Some(username)) => Reply::List(
+                self.users[&username]
+                    .rooms
+                    .iter()
+                    .map(|r| r.as_word().to_owned())
+                    .collect(),
+            ),
+            (Command::ListMembers { roomname }, Some(username)) => {
+                match self.rooms.get(&**roomname) {
+                    Some(room) if room.members.contains(&username) => Reply::List(
+                        room.members
+                            .iter()
+                            .map(|m| m.as_word().to_owned())
+                            .collect(),
+                    ),
+                    _ => error("Not in that room"),
+                }
+            }
+            (Command::CreateRoom, Some(username)) => {
+                let roomname: Box<RoomName> = format!("r{}", self.next_room).try_into().unwrap();
+                self.next_room += 1;
+
+                let mut room = Room::default();
+                room.members.insert(username.clone());
+                self.rooms.insert(roomname.clone(), room);
+                self.users
+                    .get_mut(&username)
+                    .unwrap()
+                    .rooms
+                    .insert(roomname.clone());
+                Reply::Name(roomname.into_boxed_word())
+            }
+            (Command::LeaveRoom(roomname), Some(username)) => {
+                let removed = match self.rooms.get_mut(&**roomname) {
+                    Some(room) => room.members.remove(&username),
+                    None => false,
+                };
+                if !removed {
+                    return error("Not in that room");
+                }
+                self.users
+                    .get_mut(&username)
+                    .unwrap()
+                    .rooms
+                    .remove(&**roomname);
+
+                let push = PushMessage::Leave {
+                    roomname: roomname.clone().into_owned(),
+                    username: username.clone(),
+                };
+                self.push_to_room(roomname, &push, None);
+                self.push_to_user(&username, &push, Some(session));
+                Reply::Ok
+            }
+            (
+                Command::Invite {
+                    roomname,
+                    username: invitee,
+                },
+                Some(username),
+            ) => {
+                match self.rooms.get(&**roomname) {
+                    Some(room) if room.members.contains(&username) => {}
+                    _ => return error("Not in that room"),
+                }
+                if !self.users.contains_key(&**invitee) {
+                    return error("User not found");
+                }
+                if self.rooms[&**roomname].members.contains(&**invitee) {
+                    return error("User already in room");
+                }
+
+                let join = PushMessage::Join {
+                    roomname: roomname.clone().into_owned(),
+                    username: invitee.clone().into_owned(),
+                };
+                self.push_to_room(roomname, &join, Some(session));
+
+                self.rooms
+                    .get_mut(&**roomname)
+                    .unwrap()
+                    .members
+                    .insert(invitee.clone().into_owned());
+                self.users
+                    .get_mut(&**invitee)
+                    .unwrap()
+                    .rooms
+                    .insert(roomname.clone().into_owned());
+
+                let invite = PushMessage::Invite {
+                    roomname: roomname.clone().into_owned(),
+                    inviter: username,
+                };
+                self.push_to_user(invitee, &invite, None);
+                Reply::Ok
+            }
+            (
+                Command::Send {
+                    roomname,
+                    reply_on,
+                    message,
+                },
+                Some(username),
+            ) => {
+                match self.rooms.get(&**roomname) {
+                    Some(room) if room.members.contains(&username) => {}
+                    _ => return error("Not in that room"),
+                }
+                if let Some(reply_on) = reply_on {
+                    match self.messages.get(i64::from(reply_on) as usize) {
+                        Some(m) if *m.roomname == **roomname => {}
+                        _ => return error("Replied-to message not found"),
+                    }
+                }
+
+                let id = Id::try_from(self.messages.len() as i64).unwrap();
+                let message = Message {
+                    id,
+                    reply_on: *reply_on,
+                    roomname: roomname.clone().into_owned(),
+                    username,
+                    timestamp: SystemTime::now(),
+                    message: message.clone().into_owned(),
+                };
+                self.push_to_room(
+                    roomname,
+                    &PushMessage::Message(message.clone()),
+                    Some(session),
+                );
+                self.messages.push(message);
+                self.rooms.get_mut(&**roomname).unwrap().messages.push(id);
+
+                Reply::Number(id.into())
+            }
+            (Command::SendAt { .. }, Some(_)) => error("API keys are not supported"),
+            (Command::History { roomname, count }, Some(username)) => {
+                self.history(roomname, &username, *count, None)
+            }
+            (
+                Command::HistoryBefore {
+                    roomname,
+                    count,
+                    message_id,
+                },
+                Some(username),
+            ) => self.history(roomname, &username, *count, Some(*message_id)),
+            (Command::GetMessage(id), Some(username)) => {
+                match self.messages.get(i64::from(id) as usize) {
+                    Some(m) if self.rooms[&m.roomname].members.contains(&username) => {
+                        Reply::Message(m.clone())
+                    }
+                    _ => error("Message not found"),
+                }
+            }
+            (Command::IsOnline { username }, Some(_)) => match self.users.get(&**username) {
+                Some(user) => Reply::Number(user.sessions.len() as i64),
+                None => error("User not found"),
+            },
+            (Command::FirebaseToken(_), Some(_))
+            | (Command::DeleteFirebaseToken(_), Some(_))
+            | (Command::UserActive(_), Some(_)) => Reply::Ok,
+
+            // handled above
+            (Command::Version(_), _) | (Command::Ping, _) => unreachable!(),
+        }
+    }
+
+    fn history(
+        &self,
+        roomname: &RoomName,
+        username: &Username,
+        count: i64,
+        before: Option<Id>,
+    ) -> Reply {
+        let room = match self.rooms.get(roomname) {
+            Some(room) if room.members.contains(username) => room,
+            _ => return error("Not in that room"),
+        };
+        if count < 0 {
+            return error("Negative count");
+        }
+
+        let ids = match before {
+            None => &room.messages[..],
+            Some(before) => {
+                let end = room.messages.partition_point(|id| *id < before);
+                &room.messages[..end]
+            }
+        };
+        let start = ids.len().saturating_sub(count as usize);
+        Reply::History(
+            ids[start..]
+                .iter()
+                .map(|id| self.messages[i64::from(id) as usize].clone())
+                .collect(),
+        )
+    }
+}
@@ -0,0 +1,231 @@
+//! Holds a bounded cache of messages, which resolves missing messages using
+//! `Command::GetMessage`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::oneshot;
+
+use crate::command::Command;
+use crate::connection::{Connection, Error};
+use crate::id::Id;
+use crate::message::Message;
+use crate::pushmessage::PushMessage;
+use crate::reply::Reply;
+
+/// The result of retrieving a message from the server, which is shared by every waiter.
+type Lookup = Result<Arc<Message>, Arc<Error>>;
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Every cached message, with the tick at which it was last used.
+    entries: HashMap<Id, (Arc<Message>, u64)>,
+    /// The IDs of the cached messages, keyed by the tick at which they were last used.
+    order: BTreeMap<u64, Id>,
+    tick: u64,
+    /// The waiters for every message that is currently being retrieved from the server.
+    in_flight: HashMap<Id, Vec<oneshot::Sender<Lookup>>>,
+}
+
+impl Inner {
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    fn get(&mut self, id: Id) -> Option<Arc<Message>> {
+        let tick = self.next_tick();
+        let (message, last_used) = self.entries.get_mut(&id)?;
+        self.order.remove(last_used);
+        self.order.insert(tick, id);
+        *last_used = tick;
+        Some(message.clone())
+    }
+
+    fn insert(&mut self, message: Arc<Message>, capacity: usize) {
+        let tick = self.next_tick();
+        let id = message.id;
+        if let Some((_, last_used)) = self.entries.insert(id, (message, tick)) {
+            self.order.remove(&last_used);
+        }
+        self.order.insert(tick, id);
+
+        while self.entries.len() > capacity {
+            let (_, id) = self.order.pop_first().unwrap();
+            self.entries.remove(&id);
+        }
+    }
+}
+
+/// Removes the in-flight entry of a lookup when dropped, so that waiters don't wait forever when
+/// the lookup is cancelled.
+struct InFlight<'a> {
+    cache: &'a MessageCache,
+    id: Id,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.cache.inner.lock().unwrap().in_flight.remove(&self.id);
+    }
+}
+
+/// A bounded cache of `Message` instances, which evicts the least recently used message when
+/// full.
+///
+/// The cache is filled using `MessageCache::insert` and `MessageCache::apply`, and messages
+/// that are not cached can be retrieved from the server using `MessageCache::resolve`.
+///
+/// ```
+/// use std::convert::TryFrom;
+/// use tomsg_rs::cache::MessageCache;
+/// use tomsg_rs::{Id, PushMessage};
+///
+/// let push = |id| {
+///     let line = format!("_push message room user 0 {} -1 hi", id);
+///     PushMessage::parse(&line).unwrap().unwrap()
+/// };
+///
+/// let cache = MessageCache::new(2);
+/// cache.apply(&push(1));
+/// cache.apply(&push(2));
+/// cache.get(Id::try_from(1).unwrap());
+/// cache.apply(&push(3));
+///
+/// // message 2 was used least recently, so it is evicted.
+/// assert!(cache.get(Id::try_from(1).unwrap()).is_some());
+/// assert!(cache.get(Id::try_from(2).unwrap()).is_none());
+/// ```
+#[derive(Debug)]
+pub struct MessageCache {
+    capacity: usize,
+    inner: Mutex<Inner>,
+}
+
+impl MessageCache {
+    /// Creates a new, empty `MessageCache` holding at most `capacity` messages.
+    ///
+    /// # Panics
+    /// Panics if `capacity` is zero.
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be positive");
+        Self {
+            capacity,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    /// The maximum amount of messages this `MessageCache` holds.
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The amount of messages currently in this `MessageCache`.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    /// Returns whether this `MessageCache` holds no messages.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Adds the given `message` to this `MessageCache`, evicting the least recently used message
+    /// if the cache is full.
+    pub fn insert(&self, message: Message) {
+        self.inner
+            .lock()
+            .unwrap()
+            .insert(Arc::new(message), self.capacity);
+    }
+
+    /// Adds every message in `messages`, such as the messages of a `Reply::History`.
+    pub fn extend(&self, messages: impl IntoIterator<Item = Message>) {
+        let mut inner = self.inner.lock().unwrap();
+        for message in messages {
+            inner.insert(Arc::new(message), self.capacity);
+        }
+    }
+
+    /// Adds the message in the given `push`, if it is a `PushMessage::Message`.
+    pub fn apply(&self, push: &PushMessage) {
+        if let PushMessage::Message(message) = push {
+            self.insert(message.clone());
+        }
+    }
+
+    /// Returns the cached message with the given `id`, marking it as recently used.
+    #[must_use]
+    pub fn get(&self, id: Id) -> Option<Arc<Message>> {
+        self.inner.lock().unwrap().get(id)
+    }
+
+    /// Returns the message with the given `id`, retrieving it using `Command::GetMessage` if it
+    /// is not cached.
+    ///
+    /// If the message is already being retrieved by another call, this waits for that call
+    /// instead of sending another command, and returns the same result.
+    /// A call that is waiting for a cancelled call retrieves the message itself.
+    pub async fn resolve(&self, conn: &Connection, id: Id) -> Result<Arc<Message>, Arc<Error>> {
+        loop {
+            let receiver = {
+                let mut inner = self.inner.lock().unwrap();
+                if let Some(message) = inner.get(id) {
+                    return Ok(message);
+                }
+                match inner.in_flight.get_mut(&id) {
+                    Some(waiters) => {
+                        let (sender, receiver) = oneshot::channel();
+                        waiters.push(sender);
+                        receiver
+                    }
+                    None => {
+                        inner.in_flight.insert(id, vec![]);
+                        break;
+                    }
+                }
+            };
+
+            // if the other lookup was cancelled, try again.
+            if let Ok(lookup) = receiver.await {
+                return lookup;
+            }
+        }
+
+        let in_flight = InFlight { cache: self, id };
+        let lookup = match conn.request(&Command::GetMessage(id)).await {
+            Ok(Reply::Message(m)) => Ok(Arc::new(m)),
+            Ok(r) => Err(Arc::new(Error::UnexpectedReply(r))),
+            Err(e) => Err(Arc::new(e)),
+        };
+
+        let mut inner = self.inner.lock().unwrap();
+        if let Ok(message) = &lookup {
+            inner.insert(message.clone(), self.capacity);
+        }
+        for waiter in inner.in_flight.remove(&id).into_iter().flatten() {
+            let _ = waiter.send(lookup.clone());
+        }
+        drop(inner);
+        drop(in_flight);
+
+        lookup
+    }
+
+    /// Returns the message the given `message` replies on, if any, using
+    /// `MessageCache::resolve`.
+    pub async fn resolve_parent(
+        &self,
+        conn: &Connection,
+        message: &Message,
+    ) -> Result<Option<Arc<Message>>, Arc<Error>> {
+        match message.reply_on {
+            None => Ok(None),
+            Some(id) => self.resolve(conn, id).await.map(Some),
+        }
+    }
+}
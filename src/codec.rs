@@ -0,0 +1,345 @@
+//! Holds `tokio_util::codec` implementations of the tomsg wire format.
+//!
+//! `ClientCodec` implements the client side and `ServerCodec` the server side of the protocol.
+//! These can be used together with `tokio_util::codec::Framed` to speak the tomsg protocol over
+//! any transport.
+
+use std::cmp;
+use std::convert::TryFrom;
+use std::io;
+use std::str;
+
+use bytes::{Buf, BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::command::Command;
+use crate::message::Message;
+use crate::pushmessage::{PushMessage, PushMessageRef};
+use crate::reply::{self, InternalReply, Reply};
+use crate::util::invalid_data;
+use crate::word::Word;
+
+fn invalid_input(e: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, e)
+}
+
+/// Splits lines off a buffer, limiting their length.
+#[derive(Debug)]
+struct Lines {
+    /// The index in the buffer from which to continue searching for a newline, so that the same
+    /// bytes are not searched multiple times.
+    next_index: usize,
+    max_length: usize,
+    /// Whether the rest of a line that is too long is being skipped.
+    discarding: bool,
+}
+
+impl Lines {
+    fn new(max_length: usize) -> Self {
+        Self {
+            next_index: 0,
+            max_length,
+            discarding: false,
+        }
+    }
+
+    /// Splits off the next line from `buf`, including the trailing newline.
+    ///
+    /// Fails with `io::ErrorKind::InvalidData` if the line is longer than `max_length` bytes,
+    /// after which the rest of that line is skipped.
+    fn next_line(&mut self, buf: &mut BytesMut) -> io::Result<Option<BytesMut>> {
+        loop {
+            // a line of `max_length` bytes has its newline at index `max_length`.
+            let read_to = cmp::min(self.max_length.saturating_add(1), buf.len());
+            let newline = buf[self.next_index..read_to]
+                .iter()
+                .position(|b| *b == b'\n');
+
+            match (self.discarding, newline) {
+                (true, Some(offset)) => {
+                    buf.advance(self.next_index + offset + 1);
+                    self.next_index = 0;
+                    self.discarding = false;
+                }
+                (true, None) => {
+                    buf.advance(read_to);
+                    self.next_index = 0;
+                    if buf.is_empty() {
+                        return Ok(None);
+                    }
+                }
+                (false, Some(offset)) => {
+                    let line = buf.split_to(self.next_index + offset + 1);
+                    self.next_index = 0;
+                    return Ok(Some(line));
+                }
+                (false, None) if buf.len() > self.max_length => {
+                    self.discarding = true;
+                    return Err(invalid_data("line is longer than the maximum line length"));
+                }
+                (false, None) => {
+                    self.next_index = read_to;
+                    return Ok(None);
+                }
+            }
+        }
+    }
+}
+
+/// Converts the given `line` into a `str`, without the trailing newline.
+fn line_str(line: &[u8]) -> io::Result<&str> {
+    str::from_utf8(&line[..line.len() - 1]).map_err(invalid_data)
+}
+
+/// A single item received by a tomsg client.
+#[derive(Debug, Clone)]
+pub enum ClientFrame {
+    /// A reply to the `Command` with the given `tag`.
+    Reply {
+        /// The tag of the `Command` this is a reply to.
+        tag: Box<Word>,
+        /// The reply itself.
+        reply: Reply,
+    },
+    /// The start of a history reply to the `Command` with the given `tag`.
+    ///
+    /// It is followed by `count` `ClientFrame::HistoryMessage` frames with the same `tag`.
+    History {
+        /// The tag of the `Command` this is a reply to.
+        tag: Box<Word>,
+        /// The amount of history messages that follow.
+        count: i64,
+    },
+    /// A single message of a history reply to the `Command` with the given `tag`.
+    HistoryMessage {
+        /// The tag of the `Command` this is a reply to.
+        tag: Box<Word>,
+        /// The index of this message in the history reply.
+        index: i64,
+        /// The message itself.
+        message: Message,
+    },
+    /// An item pushed by the server.
+    Push(PushMessage),
+}
+
+/// A codec for the client side of the tomsg protocol.
+///
+/// It encodes tagged `Command` instances and decodes the replies and push messages sent by the
+/// server into `ClientFrame` instances.
+/// Encoding a `Command` that can't be encoded fails with `io::ErrorKind::InvalidInput`, and
+/// decoding a line longer than the maximum line length fails with `io::ErrorKind::InvalidData`.
+///
+/// ```
+/// use bytes::BytesMut;
+/// use tokio_util::codec::Decoder;
+/// use tomsg_rs::codec::{ClientCodec, ClientFrame};
+/// use tomsg_rs::Reply;
+///
+/// let mut codec = ClientCodec::new();
+/// let mut buf = BytesMut::from("0 ok\n_push ping\n1 num");
+///
+/// match codec.decode(&mut buf).unwrap() {
+///     Some(ClientFrame::Reply { tag, reply: Reply::Ok }) => assert_eq!(tag.as_str(), "0"),
+///     _ => unreachable!(),
+/// }
+/// // the ping push is skipped, and the last line is incomplete.
+/// assert!(codec.decode(&mut buf).unwrap().is_none());
+/// ```
+#[derive(Debug)]
+pub struct ClientCodec {
+    lines: Lines,
+}
+
+impl ClientCodec {
+    /// Creates a new `ClientCodec` without a maximum line length.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::new_with_max_length(usize::MAX)
+    }
+
+    /// Creates a new `ClientCodec` which fails to decode lines longer than `max_length` bytes,
+    /// excluding the newline.
+    ///
+    /// This should be used when reading from an untrusted transport, as a peer could otherwise
+    /// make the codec buffer an unbounded amount of data.
+    #[must_use]
+    pub fn new_with_max_length(max_length: usize) -> Self {
+        Self {
+            lines: Lines::new(max_length),
+        }
+    }
+
+    /// The maximum length of a decoded line, excluding the newline.
+    #[must_use]
+    pub fn max_length(&self) -> usize {
+        self.lines.max_length
+    }
+}
+
+impl Default for ClientCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for ClientCodec {
+    type Item = ClientFrame;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> io::Result<Option<Self::Item>> {
+        while let Some(line) = self.lines.next_line(buf)? {
+            let line = line_str(&line)?;
+            if line.split(' ').next() == Some("_push") {
+                match PushMessageRef::parse(line).map_err(invalid_data)? {
+                    Some(push) => return Ok(Some(ClientFrame::Push(push.into_owned()))),
+                    None => continue, // we can ignore this push
+                }
+            }
+
+            let (tag, reply) = reply::parse(line).map_err(invalid_data)?;
+            let frame = match reply {
+                InternalReply::Normal(reply) => ClientFrame::Reply { tag, reply },
+                InternalReply::HistoryInit(count) => ClientFrame::History { tag, count },
+                InternalReply::HistoryMessage(index, message) => ClientFrame::HistoryMessage {
+                    tag,
+                    index,
+                    message,
+                },
+            };
+            return Ok(Some(frame));
+        }
+
+        Ok(None)
+    }
+}
+
+impl Encoder<(&Word, &Command<'_>)> for ClientCodec {
+    type Error = io::Error;
+
+    fn encode(
+        &mut self,
+        (tag, command): (&Word, &Command<'_>),
+        buf: &mut BytesMut,
+    ) -> io::Result<()> {
+        let command = command.encode().map_err(invalid_input)?;
+        put_line(&format!("{} {}", tag, command), buf);
+        Ok(())
+    }
+}
+
+/// A single item received by a tomsg server.
+#[derive(Debug, Clone)]
+pub struct ServerFrame {
+    /// The tag of the received command.
+    pub tag: Box<Word>,
+    /// The received `Command`, or a description of why the command line is invalid.
+    pub command: Result<Command<'static>, String>,
+}
+
+/// A codec for the server side of the tomsg protocol.
+///
+/// It decodes the tagged command lines sent by clients into `ServerFrame` instances, and
+/// encodes tagged `Reply` instances and `PushMessage` instances.
+/// Encoding an item that can't be encoded fails with `io::ErrorKind::InvalidInput`, and
+/// decoding a line longer than the maximum line length fails with `io::ErrorKind::InvalidData`.
+///
+/// ```
+/// use bytes::BytesMut;
+/// use tokio_util::codec::{Decoder, Encoder};
+/// use tomsg_rs::codec::ServerCodec;
+/// use tomsg_rs::{Command, Reply};
+///
+/// let mut codec = ServerCodec::new();
+/// let mut buf = BytesMut::from("3 ping\n");
+///
+/// let frame = codec.decode(&mut buf).unwrap().unwrap();
+/// assert_eq!(frame.command, Ok(Command::Ping));
+///
+/// codec.encode((&*frame.tag, &Reply::Pong), &mut buf).unwrap();
+/// assert_eq!(&buf[..], b"3 pong\n");
+/// ```
+#[derive(Debug)]
+pub struct ServerCodec {
+    lines: Lines,
+}
+
+impl ServerCodec {
+    /// Creates a new `ServerCodec` without a maximum line length.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::new_with_max_length(usize::MAX)
+    }
+
+    /// Creates a new `ServerCodec` which fails to decode lines longer than `max_length` bytes,
+    /// excluding the newline.
+    ///
+    /// This should be used when reading from an untrusted transport, as a peer could otherwise
+    /// make the codec buffer an unbounded amount of data.
+    #[must_use]
+    pub fn new_with_max_length(max_length: usize) -> Self {
+        Self {
+            lines: Lines::new(max_length),
+        }
+    }
+
+    /// The maximum length of a decoded line, excluding the newline.
+    #[must_use]
+    pub fn max_length(&self) -> usize {
+        self.lines.max_length
+    }
+}
+
+impl Default for ServerCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for ServerCodec {
+    type Item = ServerFrame;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> io::Result<Option<Self::Item>> {
+        let line = match self.lines.next_line(buf)? {
+            None => return Ok(None),
+            Some(line) => line,
+        };
+        let line = line_str(&line)?;
+
+        let (tag, command) = match line.find(' ') {
+            None => (line, Err(String::from("missing command"))),
+            Some(i) => (
+                &line[..i],
+                Command::parse(&line[i + 1..]).map(Command::into_owned),
+            ),
+        };
+        let tag = Box::<Word>::try_from(tag.to_owned()).map_err(invalid_data)?;
+
+        Ok(Some(ServerFrame { tag, command }))
+    }
+}
+
+impl Encoder<(&Word, &Reply)> for ServerCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, (tag, reply): (&Word, &Reply), buf: &mut BytesMut) -> io::Result<()> {
+        put_line(&reply.encode(tag).map_err(invalid_input)?, buf);
+        Ok(())
+    }
+}
+
+impl Encoder<&PushMessage> for ServerCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, push: &PushMessage, buf: &mut BytesMut) -> io::Result<()> {
+        put_line(&push.encode().map_err(invalid_input)?, buf);
+        Ok(())
+    }
+}
+
+fn put_line(line: &str, buf: &mut BytesMut) {
+    buf.reserve(line.len() + 1);
+    buf.put_slice(line.as_bytes());
+    buf.put_u8(b'\n');
+}
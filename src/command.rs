@@ -1,69 +1,89 @@
 use std::borrow::Cow;
+use std::convert::TryFrom;
 use std::time;
 
 use crate::id::Id;
 use crate::line::Line;
+use crate::names::{ApiKey, FirebaseToken, RoomName, Username};
+use crate::timestamp;
+use crate::util::{encode_optional_id, Words};
 use crate::word::Word;
 
 /// A command that is sendable to a tomsg server, with related information.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Command<'a> {
     Version(Cow<'a, Word>),
     Register {
-        username: Cow<'a, Word>,
+        username: Cow<'a, Username>,
         password: Cow<'a, Line>,
     },
     Login {
-        username: Cow<'a, Word>,
+        username: Cow<'a, Username>,
         password: Cow<'a, Line>,
     },
     ChangePassword(Cow<'a, Line>),
     Logout,
     ListRooms,
     ListMembers {
-        roomname: Cow<'a, Word>,
+        roomname: Cow<'a, RoomName>,
     },
     CreateRoom,
-    LeaveRoom(Cow<'a, Word>),
+    LeaveRoom(Cow<'a, RoomName>),
     Invite {
-        roomname: Cow<'a, Word>,
-        username: Cow<'a, Word>,
+        roomname: Cow<'a, RoomName>,
+        username: Cow<'a, Username>,
     },
     Send {
-        roomname: Cow<'a, Word>,
+        roomname: Cow<'a, RoomName>,
         reply_on: Option<Id>,
         message: Cow<'a, Line>,
     },
     SendAt {
-        apikey: Cow<'a, Word>,
-        roomname: Cow<'a, Word>,
+        apikey: Cow<'a, ApiKey>,
+        roomname: Cow<'a, RoomName>,
         reply_on: Option<Id>,
         timestamp: time::SystemTime,
         message: Cow<'a, Line>,
     },
     History {
-        roomname: Cow<'a, Word>,
+        roomname: Cow<'a, RoomName>,
         count: i64,
     },
     HistoryBefore {
-        roomname: Cow<'a, Word>,
+        roomname: Cow<'a, RoomName>,
         count: i64,
         message_id: Id,
     },
     GetMessage(Id),
     Ping,
     IsOnline {
-        username: Cow<'a, Word>,
+        username: Cow<'a, Username>,
     },
-    FirebaseToken(Cow<'a, Word>),
-    DeleteFirebaseToken(Cow<'a, Word>),
+    FirebaseToken(Cow<'a, FirebaseToken>),
+    DeleteFirebaseToken(Cow<'a, FirebaseToken>),
     UserActive(i64),
 }
 
 impl<'a> Command<'a> {
-    #[allow(clippy::inherent_to_string)]
-    pub(super) fn to_string(&self) -> String {
-        match self {
+    /// Encodes this `Command` into a command line, without tag and trailing newline.
+    ///
+    /// Fails if the command contains a timestamp that can't be encoded, such as a timestamp
+    /// before the UNIX epoch.
+    ///
+    /// ```
+    /// use tomsg_rs::{Command, RoomName};
+    /// use std::convert::TryFrom;
+    ///
+    /// let roomname = <&RoomName>::try_from("room").unwrap();
+    /// let command = Command::History {
+    ///     roomname: roomname.into(),
+    ///     count: 10,
+    /// };
+    /// assert_eq!(command.encode().unwrap(), "history room 10");
+    /// ```
+    pub fn encode(&self) -> Result<String, &'static str> {
+        let res = match self {
             Command::Version(v) => format!("version {}", v),
             Command::Register { username, password } => {
                 format!("register {} {}", username, password)
@@ -80,36 +100,26 @@ impl<'a> Command<'a> {
                 roomname,
                 reply_on,
                 message,
-            } => {
-                let reply_on: i64 = match reply_on {
-                    None => -1,
-                    Some(id) => id.into(),
-                };
-                format!("send {} {} {}", roomname, reply_on, message)
-            }
+            } => format!(
+                "send {} {} {}",
+                roomname,
+                encode_optional_id(*reply_on),
+                message
+            ),
             Command::SendAt {
                 apikey,
                 roomname,
                 reply_on,
                 timestamp,
                 message,
-            } => {
-                let reply_on: i64 = match reply_on {
-                    None => -1,
-                    Some(id) => id.into(),
-                };
-                format!(
-                    "sendat {} {} {} {} {}",
-                    apikey,
-                    roomname,
-                    reply_on,
-                    timestamp
-                        .duration_since(time::UNIX_EPOCH)
-                        .unwrap()
-                        .as_micros(),
-                    message
-                )
-            }
+            } => format!(
+                "sendat {} {} {} {} {}",
+                apikey,
+                roomname,
+                encode_optional_id(*reply_on),
+                timestamp::to_micros(*timestamp)?,
+                message
+            ),
             Command::History { roomname, count } => format!("history {} {}", roomname, count),
             Command::HistoryBefore {
                 roomname,
@@ -122,6 +132,200 @@ impl<'a> Command<'a> {
             Command::FirebaseToken(token) => format!("firebase_token {}", token),
             Command::DeleteFirebaseToken(token) => format!("delete_firebase_token {}", token),
             Command::UserActive(active) => format!("user_active {}", active),
+        };
+        Ok(res)
+    }
+
+    /// Parses the given command `line`, without tag and trailing newline, into a `Command`.
+    ///
+    /// The returned `Command` borrows from `line`, use `Command::into_owned` to get a `Command`
+    /// that doesn't.
+    ///
+    /// ```
+    /// use tomsg_rs::Command;
+    ///
+    /// let command = Command::parse("send room -1 hello world").unwrap();
+    /// assert_eq!(command.encode().unwrap(), "send room -1 hello world");
+    ///
+    /// assert!(Command::parse("send room").is_err());
+    /// assert!(Command::parse("unknown_command").is_err());
+    /// ```
+    pub fn parse(line: &'a str) -> Result<Self, String> {
+        let mut words = Words::new(line);
+
+        let command = match words.word("command")?.as_str() {
+            "version" => Command::Version(words.word("version")?.into()),
+            "register" => Command::Register {
+                username: Cow::Borrowed(words.word("username")?.into()),
+                password: words.rest("password")?.into(),
+            },
+            "login" => Command::Login {
+                username: Cow::Borrowed(words.word("username")?.into()),
+                password: words.rest("password")?.into(),
+            },
+            "change_password" => Command::ChangePassword(words.rest("password")?.into()),
+            "logout" => Command::Logout,
+            "list_rooms" => Command::ListRooms,
+            "list_members" => Command::ListMembers {
+                roomname: Cow::Borrowed(words.word("roomname")?.into()),
+            },
+            "create_room" => Command::CreateRoom,
+            "leave_room" => Command::LeaveRoom(Cow::Borrowed(words.word("roomname")?.into())),
+            "invite" => Command::Invite {
+                roomname: Cow::Borrowed(words.word("roomname")?.into()),
+                username: Cow::Borrowed(words.word("username")?.into()),
+            },
+            "send" => Command::Send {
+                roomname: Cow::Borrowed(words.word("roomname")?.into()),
+                reply_on: words.optional_id("reply_on")?,
+                message: words.rest("message")?.into(),
+            },
+            "sendat" => Command::SendAt {
+                apikey: Cow::Borrowed(words.word("apikey")?.into()),
+                roomname: Cow::Borrowed(words.word("roomname")?.into()),
+                reply_on: words.optional_id("reply_on")?,
+                timestamp: words.timestamp("timestamp")?,
+                message: words.rest("message")?.into(),
+            },
+            "history" => Command::History {
+                roomname: Cow::Borrowed(words.word("roomname")?.into()),
+                count: words.number("count")?,
+            },
+            "history_before" => Command::HistoryBefore {
+                roomname: Cow::Borrowed(words.word("roomname")?.into()),
+                count: words.number("count")?,
+                message_id: words.id("message_id")?,
+            },
+            "get_message" => Command::GetMessage(words.id("message_id")?),
+            "ping" => Command::Ping,
+            "is_online" => Command::IsOnline {
+                username: Cow::Borrowed(words.word("username")?.into()),
+            },
+            "firebase_token" => Command::FirebaseToken(Cow::Borrowed(words.word("token")?.into())),
+            "delete_firebase_token" => {
+                Command::DeleteFirebaseToken(Cow::Borrowed(words.word("token")?.into()))
+            }
+            "user_active" => Command::UserActive(words.number("active")?),
+            c => return Err(format!("unknown command: '{}'", c)),
+        };
+
+        words.end()?;
+        Ok(command)
+    }
+
+    /// Returns a copy of this `Command` in which passwords and API keys are replaced by
+    /// `<redacted>`, which is useful for logging.
+    ///
+    /// ```
+    /// use tomsg_rs::Command;
+    ///
+    /// let command = Command::parse("login user hunter2").unwrap();
+    /// assert_eq!(command.redacted().encode().unwrap(), "login user <redacted>");
+    /// ```
+    #[must_use]
+    pub fn redacted(&self) -> Command<'_> {
+        let apikey = <&ApiKey>::try_from("<redacted>").unwrap();
+        let line = <&Line>::try_from("<redacted>").unwrap();
+
+        match self {
+            Command::Register { username, .. } => Command::Register {
+                username: Cow::Borrowed(username),
+                password: line.into(),
+            },
+            Command::Login { username, .. } => Command::Login {
+                username: Cow::Borrowed(username),
+                password: line.into(),
+            },
+            Command::ChangePassword(_) => Command::ChangePassword(line.into()),
+            Command::SendAt {
+                roomname,
+                reply_on,
+                timestamp,
+                message,
+                ..
+            } => Command::SendAt {
+                apikey: apikey.into(),
+                roomname: Cow::Borrowed(roomname),
+                reply_on: *reply_on,
+                timestamp: *timestamp,
+                message: Cow::Borrowed(message),
+            },
+            c => c.clone(),
+        }
+    }
+
+    /// Converts this `Command` into a `Command` that doesn't borrow any data.
+    #[must_use]
+    pub fn into_owned(self) -> Command<'static> {
+        fn owned<T: ToOwned + ?Sized>(c: Cow<'_, T>) -> Cow<'static, T> {
+            Cow::Owned(c.into_owned())
+        }
+
+        match self {
+            Command::Version(v) => Command::Version(owned(v)),
+            Command::Register { username, password } => Command::Register {
+                username: owned(username),
+                password: owned(password),
+            },
+            Command::Login { username, password } => Command::Login {
+                username: owned(username),
+                password: owned(password),
+            },
+            Command::ChangePassword(password) => Command::ChangePassword(owned(password)),
+            Command::Logout => Command::Logout,
+            Command::ListRooms => Command::ListRooms,
+            Command::ListMembers { roomname } => Command::ListMembers {
+                roomname: owned(roomname),
+            },
+            Command::CreateRoom => Command::CreateRoom,
+            Command::LeaveRoom(roomname) => Command::LeaveRoom(owned(roomname)),
+            Command::Invite { roomname, username } => Command::Invite {
+                roomname: owned(roomname),
+                username: owned(username),
+            },
+            Command::Send {
+                roomname,
+                reply_on,
+                message,
+            } => Command::Send {
+                roomname: owned(roomname),
+                reply_on,
+                message: owned(message),
+            },
+            Command::SendAt {
+                apikey,
+                roomname,
+                reply_on,
+                timestamp,
+                message,
+            } => Command::SendAt {
+                apikey: owned(apikey),
+                roomname: owned(roomname),
+                reply_on,
+                timestamp,
+                message: owned(message),
+            },
+            Command::History { roomname, count } => Command::History {
+                roomname: owned(roomname),
+                count,
+            },
+            Command::HistoryBefore {
+                roomname,
+                count,
+                message_id,
+            } => Command::HistoryBefore {
+                roomname: owned(roomname),
+                count,
+                message_id,
+            },
+            Command::GetMessage(message_id) => Command::GetMessage(message_id),
+            Command::Ping => Command::Ping,
+            Command::IsOnline { username } => Command::IsOnline {
+                username: owned(username),
+            },
+            Command::FirebaseToken(token) => Command::FirebaseToken(owned(token)),
+            Command::DeleteFirebaseToken(token) => Command::DeleteFirebaseToken(owned(token)),
+            Command::UserActive(active) => Command::UserActive(active),
         }
     }
 }
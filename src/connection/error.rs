@@ -0,0 +1,53 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+use super::CloseReason;
+use crate::line::Line;
+use crate::reply::Reply;
+
+/// An error that occured while sending a `Command` and receiving its reply.
+#[derive(Debug)]
+pub enum Error {
+    /// Writing the `Command` to the connection socket failed.
+    Io(io::Error),
+    /// The `Connection` was closed before the reply was received.
+    Closed(CloseReason),
+    /// The server replied with `Reply::Error`.
+    Server(Box<Line>),
+    /// The server replied with a `Reply` which wasn't expected for the sent `Command`.
+    UnexpectedReply(Reply),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Closed(CloseReason::EOF) => write!(f, "connection closed: EOF"),
+            Error::Closed(CloseReason::Err(e)) => write!(f, "connection closed: {}", e),
+            Error::Server(e) => write!(f, "server error: {}", e),
+            Error::UnexpectedReply(r) => write!(f, "unexpected reply: {:?}", r),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<CloseReason> for Error {
+    fn from(close_reason: CloseReason) -> Self {
+        Error::Closed(close_reason)
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::{HashSet, VecDeque};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use futures_util::stream;
+use tokio::sync::mpsc;
+
+use super::{Connection, Error};
+use crate::command::Command;
+use crate::id::Id;
+use crate::message::Message;
+use crate::names::RoomName;
+use crate::reply::Reply;
+
+/// A `Stream` of historical `Message` instances, yielded as they are received from the server.
+///
+/// The stream ends after `total` messages have been yielded, or earlier if the `Connection` is
+/// closed before all messages were received.
+#[derive(Debug)]
+pub struct HistoryStream {
+    count: i64,
+    receiver: mpsc::UnboundedReceiver<Message>,
+}
+
+impl HistoryStream {
+    pub(super) fn new(count: i64, receiver: mpsc::UnboundedReceiver<Message>) -> Self {
+        Self { count, receiver }
+    }
+
+    /// The amount of messages the server announced it will send.
+    #[must_use]
+    pub fn total(&self) -> i64 {
+        self.count
+    }
+}
+
+impl Stream for HistoryStream {
+    type Item = Message;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+/// A reply to a `Command` sent using `Connection::send_streamed`.
+#[derive(Debug)]
+pub enum StreamedReply {
+    /// The server replied with history, the `Message` instances are streamed as they arrive.
+    History(HistoryStream),
+    /// The server replied with any other reply, for example `Reply::Error`.
+    Reply(Reply),
+}
+
+impl StreamedReply {
+    #[must_use]
+    pub fn history(self) -> Option<HistoryStream> {
+        match self {
+            StreamedReply::History(h) => Some(h),
+            _ => None,
+        }
+    }
+    #[must_use]
+    pub fn reply(self) -> Option<Reply> {
+        match self {
+            StreamedReply::Reply(r) => Some(r),
+            _ => None,
+        }
+    }
+}
+
+struct PagesState<'a> {
+    conn: &'a Connection,
+    roomname: Box<RoomName>,
+    page_size: i64,
+    /// The oldest message ID seen so far, or `None` if no page has been fetched yet.
+    before: Option<Id>,
+    /// The IDs of the messages in the previously fetched page.
+    seen: HashSet<Id>,
+    /// The messages of the current page that still have to be yielded, newest first.
+    buffer: VecDeque<Message>,
+    done: bool,
+}
+
+impl PagesState<'_> {
+    async fn fetch_page(&mut self) -> Result<(), Error> {
+        let command = match self.before {
+            None => Command::History {
+                roomname: (&*self.roomname).into(),
+                count: self.page_size,
+            },
+            Some(message_id) => Command::HistoryBefore {
+                roomname: (&*self.roomname).into(),
+                count: self.page_size,
+                message_id,
+            },
+        };
+        let page = match self.conn.request(&command).await? {
+            Reply::History(page) => page,
+            r => return Err(Error::UnexpectedReply(r)),
+        };
+
+        // a page that isn't full means that we reached the first message of the room.
+        self.done = (page.len() as i64) < self.page_size;
+
+        let before = self.before;
+        let seen = &self.seen;
+        let page: Vec<_> = page
+            .into_iter()
+            .filter(|m| before.is_none_or(|id| m.id < id) && !seen.contains(&m.id))
+            .collect();
+
+        match page.iter().map(|m| m.id).min() {
+            // the page didn't contain any new messages, so we can't get any further.
+            None => self.done = true,
+            Some(oldest) => self.before = Some(oldest),
+        }
+
+        self.seen = page.iter().map(|m| m.id).collect();
+        self.buffer.extend(page.into_iter().rev());
+        Ok(())
+    }
+}
+
+pub(super) fn pages<'a>(
+    conn: &'a Connection,
+    roomname: Box<RoomName>,
+    before: Option<Id>,
+    page_size: i64,
+) -> impl Stream<Item = Result<Message, Error>> + 'a {
+    assert!(page_size > 0, "page_size must be positive");
+
+    let state = PagesState {
+        conn,
+        roomname,
+        page_size,
+        before,
+        seen: HashSet::new(),
+        buffer: VecDeque::new(),
+        done: false,
+    };
+
+    stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(message) = state.buffer.pop_front() {
+                return Some((Ok(message), state));
+            }
+            if state.done {
+                return None;
+            }
+            if let Err(e) = state.fetch_page().await {
+                state.done = true;
+                return Some((Err(e), state));
+            }
+        }
+    })
+}
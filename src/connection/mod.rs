@@ -1,38 +1,88 @@
 //! Holds connection related data types.
 
 mod closereason;
+mod error;
+mod history;
+mod trace;
 mod r#type;
 
 pub use self::closereason::*;
+pub use self::error::*;
+pub use self::history::*;
 pub use self::r#type::*;
+pub use self::trace::*;
 
 use std::collections::HashMap;
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 use std::future::Future;
 use std::io;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
 
+use futures_core::Stream;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::tcp::OwnedWriteHalf;
 use tokio::net::{lookup_host, TcpStream, ToSocketAddrs};
 use tokio::sync::{mpsc, oneshot, Mutex};
 
 use crate::command::Command;
+use crate::id::Id;
+use crate::line::Line;
 use crate::message::Message;
+use crate::names::RoomName;
 use crate::pushmessage::*;
 use crate::reply::*;
+use crate::util::expect_word;
 use crate::word::Word;
 
+/// A sent `Command` that is waiting for (the rest of) its reply.
+enum Pending {
+    /// Waiting for a reply, which is delivered in full.
+    Reply(oneshot::Sender<Result<Reply, CloseReason>>),
+    /// Waiting for a reply, of which history is delivered as a `HistoryStream`.
+    Streamed(oneshot::Sender<Result<StreamedReply, CloseReason>>),
+    /// Collecting history messages, which are delivered when all of them are received.
+    History {
+        count: i64,
+        items: Vec<Message>,
+        sender: oneshot::Sender<Result<Reply, CloseReason>>,
+    },
+    /// Forwarding history messages to a `HistoryStream`.
+    HistoryStream {
+        count: i64,
+        sender: mpsc::UnboundedSender<Message>,
+    },
+    /// Waiting for a reply to a raw command line, which is delivered unparsed.
+    Raw(oneshot::Sender<Result<Vec<Box<Word>>, CloseReason>>),
+}
+
+impl Pending {
+    fn close(self, close_reason: CloseReason) {
+        // the receiving end could have been dropped, we don't care about that.
+        match self {
+            Pending::Reply(sender) | Pending::History { sender, .. } => {
+                let _ = sender.send(Err(close_reason));
+            }
+            Pending::Streamed(sender) => {
+                let _ = sender.send(Err(close_reason));
+            }
+            Pending::HistoryStream { .. } => {}
+            Pending::Raw(sender) => {
+                let _ = sender.send(Err(close_reason));
+            }
+        }
+    }
+}
+
 struct ConnectionInternal {
     tag_counter: usize,
-    reply_map: HashMap<Box<Word>, oneshot::Sender<Result<Reply, CloseReason>>>,
-    awaiting_history: Option<(i64, Vec<Message>)>,
+    reply_map: HashMap<Box<Word>, Pending>,
     push_channel: Result<mpsc::Sender<PushMessage>, CloseReason>,
 }
 
 impl ConnectionInternal {
     async fn handle_message(&mut self, message: String) {
-        if message.splitn(2, ' ').next() == Some("_push") {
+        if message.split(' ').next() == Some("_push") {
             self.handle_push(message).await;
         } else {
             self.handle_reply(message).await;
@@ -41,8 +91,9 @@ impl ConnectionInternal {
 
     async fn handle_push(&mut self, message: String) {
         let push = match PushMessage::parse(&message) {
-            Some(p) => p,
-            None => return, // we can ignore this push
+            Ok(Some(p)) => p,
+            Ok(None) => return, // we can ignore this push
+            Err(_) => return,   // the push is invalid, we can't do anything with it
         };
         self.push_channel
             .as_mut()
@@ -53,42 +104,113 @@ impl ConnectionInternal {
     }
 
     async fn handle_reply(&mut self, message: String) {
-        let reply = parse(&message);
+        let mut words = message.split(' ');
+        let tag = <&Word>::try_from(words.next().unwrap()).ok();
+        if let Some(tag) = tag {
+            if matches!(self.reply_map.get(tag), Some(Pending::Raw(_))) {
+                if let Some(Pending::Raw(sender)) = self.reply_map.remove(tag) {
+                    let _ = sender.send(Ok(words.map(expect_word).collect()));
+                }
+                return;
+            }
+        }
+
+        let (tag, reply) = match parse(&message) {
+            Ok(r) => r,
+            Err(e) => {
+                // the reply is invalid, fail the command waiting for it instead of letting it
+                // wait forever.
+                if let Some(pending) = tag.and_then(|tag| self.reply_map.remove(tag)) {
+                    pending.close(CloseReason::Err(format!("invalid reply: {}", e)));
+                }
+                return;
+            }
+        };
+        let pending = match self.reply_map.remove(&tag) {
+            Some(p) => p,
+            None => return, // nobody is waiting for this reply
+        };
+
+        // the receiving ends could have been dropped, we don't care about that.
+        let pending = match (reply, pending) {
+            (InternalReply::Normal(n), Pending::Reply(sender)) => {
+                let _ = sender.send(Ok(n));
+                None
+            }
+            (InternalReply::Normal(n), Pending::Streamed(sender)) => {
+                let _ = sender.send(Ok(StreamedReply::Reply(n)));
+                None
+            }
 
-        match reply.1 {
-            InternalReply::HistoryInit(count) => {
+            (InternalReply::HistoryInit(count), Pending::Reply(sender)) => {
                 if count == 0 {
-                    if let Some(sender) = self.reply_map.remove(&reply.0) {
-                        sender.send(Ok(Reply::History(vec![]))).unwrap();
-                    }
+                    let _ = sender.send(Ok(Reply::History(vec![])));
+                    None
                 } else {
-                    self.awaiting_history = Some((count, Vec::with_capacity(count as usize)));
+                    Some(Pending::History {
+                        count,
+                        // don't trust the server with the amount of memory to allocate.
+                        items: Vec::with_capacity(count.min(1024) as usize),
+                        sender,
+                    })
                 }
             }
-            InternalReply::HistoryMessage(index, message) => {
-                match &self.awaiting_history {
-                    None => panic!("not waiting"),
-                    Some((count, items)) => {
-                        let mut items = items.clone(); // REVIEW
-                        items.push(message);
-                        if index == *count - 1 {
-                            // done
-                            self.awaiting_history = None;
-
-                            if let Some(sender) = self.reply_map.remove(&reply.0) {
-                                sender.send(Ok(Reply::History(items))).unwrap();
-                            }
-                        } else {
-                            self.awaiting_history = Some((*count, items));
-                        }
-                    }
+            (InternalReply::HistoryInit(count), Pending::Streamed(sender)) => {
+                let (history_send, history_receive) = mpsc::unbounded_channel();
+                let _ = sender.send(Ok(StreamedReply::History(HistoryStream::new(
+                    count,
+                    history_receive,
+                ))));
+                if count == 0 {
+                    None
+                } else {
+                    Some(Pending::HistoryStream {
+                        count,
+                        sender: history_send,
+                    })
                 }
             }
-            InternalReply::Normal(n) => {
-                if let Some(sender) = self.reply_map.remove(&reply.0) {
-                    sender.send(Ok(n)).unwrap();
+
+            (
+                InternalReply::HistoryMessage(index, message),
+                Pending::History {
+                    count,
+                    mut items,
+                    sender,
+                },
+            ) => {
+                items.push(message);
+                if index == count - 1 {
+                    // done
+                    let _ = sender.send(Ok(Reply::History(items)));
+                    None
+                } else {
+                    Some(Pending::History {
+                        count,
+                        items,
+                        sender,
+                    })
                 }
             }
+            (
+                InternalReply::HistoryMessage(index, message),
+                Pending::HistoryStream { count, sender },
+            ) => {
+                let _ = sender.send(message);
+                if index == count - 1 {
+                    // done
+                    None
+                } else {
+                    Some(Pending::HistoryStream { count, sender })
+                }
+            }
+
+            // the reply doesn't match what we are waiting for, keep waiting.
+            (_, pending) => Some(pending),
+        };
+
+        if let Some(pending) = pending {
+            self.reply_map.insert(tag, pending);
         }
     }
 }
@@ -97,6 +219,41 @@ impl ConnectionInternal {
 pub struct Connection {
     stream: Arc<Mutex<OwnedWriteHalf>>,
     internal: Arc<Mutex<ConnectionInternal>>,
+    tracer: Arc<RwLock<Option<Arc<dyn Tracer>>>>,
+}
+
+fn trace_incoming(tracer: &RwLock<Option<Arc<dyn Tracer>>>, line: &str) {
+    let tracer = match &*tracer.read().unwrap() {
+        None => return,
+        Some(t) => t.clone(),
+    };
+
+    let (tag, line) = match line.find(' ') {
+        Some(i) if &line[..i] != "_push" => (<&Word>::try_from(&line[..i]).ok(), &line[i + 1..]),
+        _ => (None, line),
+    };
+    tracer.trace(&TraceEvent {
+        direction: Direction::Incoming,
+        timestamp: SystemTime::now(),
+        tag,
+        line,
+    });
+}
+
+/// Returns the redacted form of a raw command `line`.
+///
+/// A line that `Command::parse` rejects is traced as is, unless its command carries
+/// credentials, in which case everything after the command word is redacted.
+fn redact_raw(line: &Line) -> String {
+    if let Ok(command) = Command::parse(line) {
+        return command.redacted().encode().unwrap_or_default();
+    }
+    match line.split(' ').next().unwrap_or_default() {
+        command @ ("register" | "login" | "change_password" | "sendat") => {
+            format!("{} <redacted>", command)
+        }
+        _ => line.to_string(),
+    }
 }
 
 impl Connection {
@@ -122,14 +279,16 @@ impl Connection {
         let internal = Arc::new(Mutex::new(ConnectionInternal {
             tag_counter: 0,
             reply_map: HashMap::new(),
-            awaiting_history: None,
             push_channel: Ok(push_send),
         }));
 
+        let tracer: Arc<RwLock<Option<Arc<dyn Tracer>>>> = Arc::new(RwLock::new(None));
+
         let conn = Self {
             stream: Arc::new(Mutex::new(writer)),
 
             internal: internal.clone(),
+            tracer: tracer.clone(),
         };
 
         tokio::spawn(async move {
@@ -138,6 +297,11 @@ impl Connection {
             let (mut internal, close_reason) = loop {
                 let mut line = String::new();
                 let res = reader.read_line(&mut line).await;
+                if let Ok(1..) = res {
+                    line.pop();
+                    // trace before taking the lock, so a slow tracer doesn't block senders.
+                    trace_incoming(&tracer, &line);
+                }
 
                 let mut internal = internal.lock().await;
                 match res {
@@ -152,15 +316,12 @@ impl Connection {
                         internal.push_channel = Err(close_reason.clone());
                         break (internal, close_reason);
                     }
-                    Ok(_) => {
-                        line.pop();
-                        internal.handle_message(line).await;
-                    }
+                    Ok(_) => internal.handle_message(line).await,
                 }
             };
 
-            for (_, ch) in internal.reply_map.drain() {
-                ch.send(Err(close_reason.clone())).unwrap();
+            for (_, pending) in internal.reply_map.drain() {
+                pending.close(close_reason.clone());
             }
         });
 
@@ -179,38 +340,171 @@ impl Connection {
     }
 
     /// Send the given `command` to this `Connection`.
+    ///
+    /// Fails with `io::ErrorKind::InvalidInput` if the `command` can't be encoded.
+    /// If the server sends a reply that can't be parsed, `CloseReason::Err` is returned, even
+    /// though the connection stays open.
     pub fn send<'a, 'b>(
         &'a self,
         command: &'b Command<'b>,
     ) -> impl Future<Output = tokio::io::Result<Result<Reply, CloseReason>>> + 'a {
-        let command = command.to_string();
+        let traced = self.traced(|| command.redacted().encode().unwrap_or_default());
+        self.send_line(command.encode(), traced, Pending::Reply)
+    }
 
-        async move {
-            let (tag, receiver) = {
-                let mut internal = self.internal.lock().await;
+    /// Send the given `command` to this `Connection`, streaming history replies.
+    ///
+    /// This is useful for `Command::History` and `Command::HistoryBefore` with a large `count`:
+    /// instead of waiting for all messages to be received, every `Message` is yielded by the
+    /// returned `HistoryStream` as soon as it arrives.
+    /// Any other reply, such as `Reply::Error`, is returned as `StreamedReply::Reply`.
+    ///
+    /// Fails with `io::ErrorKind::InvalidInput` if the `command` can't be encoded.
+    pub fn send_streamed<'a, 'b>(
+        &'a self,
+        command: &'b Command<'b>,
+    ) -> impl Future<Output = tokio::io::Result<Result<StreamedReply, CloseReason>>> + 'a {
+        let traced = self.traced(|| command.redacted().encode().unwrap_or_default());
+        self.send_line(command.encode(), traced, Pending::Streamed)
+    }
 
-                let tag = internal.tag_counter;
-                let tag: Box<Word> = tag.to_string().try_into().unwrap();
-                internal.tag_counter = internal.tag_counter.overflowing_add(1).0;
+    /// Send the given raw command `line` to this `Connection`.
+    ///
+    /// This can be used for commands that `Command` can't express, such as extensions of a
+    /// specific server.
+    /// The words of the reply, excluding the tag, are returned unparsed.
+    /// Only the first line of the reply is returned, replies spanning multiple lines (such as
+    /// history) are not supported.
+    ///
+    /// The line given to the `Tracer` is redacted like a `Command`; if `Command::parse` rejects
+    /// it, everything after the command word of a command carrying credentials is redacted, and
+    /// any other line is traced as is.
+    /// Use `Connection::send_raw_redacted` to send a line containing credentials that
+    /// `Command` doesn't know about.
+    pub fn send_raw<'a>(
+        &'a self,
+        line: &Line,
+    ) -> impl Future<Output = tokio::io::Result<Result<Vec<Box<Word>>, CloseReason>>> + 'a {
+        let traced = self.traced(|| redact_raw(line));
+        self.send_line(Ok(line.to_string()), traced, Pending::Raw)
+    }
 
-                let (sender, receiver) = oneshot::channel();
-                if internal.reply_map.insert(tag.clone(), sender).is_some() {
-                    // this shouldn't be possible.
-                    panic!("key already exists");
-                }
-                (tag, receiver)
-            };
+    /// Send the given raw command `line` to this `Connection`, like `Connection::send_raw`, but
+    /// give `redacted` to the `Tracer` instead of `line`.
+    pub fn send_raw_redacted<'a>(
+        &'a self,
+        line: &Line,
+        redacted: &Line,
+    ) -> impl Future<Output = tokio::io::Result<Result<Vec<Box<Word>>, CloseReason>>> + 'a {
+        let traced = self.traced(|| redacted.to_string());
+        self.send_line(Ok(line.to_string()), traced, Pending::Raw)
+    }
 
+    /// Walk the history of the room with the given `roomname` backwards, starting at the newest
+    /// message.
+    ///
+    /// The history is fetched in pages of `page_size` messages using `Command::History` and
+    /// `Command::HistoryBefore`, and the returned `Stream` yields the messages newest first until
+    /// the first message of the room is reached.
+    /// Every message is yielded at most once, even if the server returns overlapping pages.
+    ///
+    /// If an error occurs the `Stream` yields it and ends.
+    ///
+    /// # Panics
+    /// Panics if `page_size` is not positive.
+    pub fn history_pages<'a>(
+        &'a self,
+        roomname: &RoomName,
+        page_size: i64,
+    ) -> impl Stream<Item = Result<Message, Error>> + 'a {
+        history::pages(self, roomname.to_owned(), None, page_size)
+    }
+
+    /// Walk the history of the room with the given `roomname` backwards, like
+    /// `Connection::history_pages`, but starting at the message before the message with ID
+    /// `before`.
+    ///
+    /// # Panics
+    /// Panics if `page_size` is not positive.
+    pub fn history_pages_before<'a>(
+        &'a self,
+        roomname: &RoomName,
+        before: Id,
+        page_size: i64,
+    ) -> impl Stream<Item = Result<Message, Error>> + 'a {
+        history::pages(self, roomname.to_owned(), Some(before), page_size)
+    }
+
+    /// Send the given `command` and wait for its reply, turning `Reply::Error` into an `Error`.
+    pub(crate) async fn request(&self, command: &Command<'_>) -> Result<Reply, Error> {
+        match self.send(command).await?? {
+            Reply::Error(e) => Err(Error::Server(e)),
+            r => Ok(r),
+        }
+    }
+
+    /// Sets the `Tracer` which observes every line sent and received by this `Connection`,
+    /// replacing the previous one.
+    ///
+    /// Passing `None` removes the current `Tracer`.
+    pub fn set_tracer(&self, tracer: Option<Arc<dyn Tracer>>) {
+        *self.tracer.write().unwrap() = tracer;
+    }
+
+    /// If a `Tracer` is set, returns it together with the redacted line created by `redact`.
+    fn traced(&self, redact: impl FnOnce() -> String) -> Option<(Arc<dyn Tracer>, String)> {
+        let tracer = self.tracer.read().unwrap().clone()?;
+        Some((tracer, redact()))
+    }
+
+    async fn send_line<T>(
+        &self,
+        command: Result<String, &'static str>,
+        traced: Option<(Arc<dyn Tracer>, String)>,
+        make_pending: impl FnOnce(oneshot::Sender<Result<T, CloseReason>>) -> Pending,
+    ) -> tokio::io::Result<Result<T, CloseReason>> {
+        let command = command.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        let (tag, receiver) = {
+            let mut internal = self.internal.lock().await;
+            if let Err(e) = &internal.push_channel {
+                return Ok(Err(e.clone()));
+            }
+
+            let tag = internal.tag_counter;
+            let tag: Box<Word> = tag.to_string().try_into().unwrap();
+            internal.tag_counter = internal.tag_counter.overflowing_add(1).0;
+
+            let (sender, receiver) = oneshot::channel();
+            if internal
+                .reply_map
+                .insert(tag.clone(), make_pending(sender))
+                .is_some()
             {
-                let mut stream = self.stream.lock().await;
-                stream
-                    .write(format!("{} {}\n", tag, command).as_bytes())
-                    .await?;
-                stream.flush().await?;
+                // this shouldn't be possible.
+                panic!("key already exists");
             }
+            (tag, receiver)
+        };
 
-            Ok(receiver.await.unwrap())
+        if let Some((tracer, line)) = traced {
+            tracer.trace(&TraceEvent {
+                direction: Direction::Outgoing,
+                timestamp: SystemTime::now(),
+                tag: Some(&tag),
+                line: &line,
+            });
         }
+
+        {
+            let mut stream = self.stream.lock().await;
+            stream
+                .write_all(format!("{} {}\n", tag, command).as_bytes())
+                .await?;
+            stream.flush().await?;
+        }
+
+        Ok(receiver.await.unwrap())
     }
 
     /// Gets the reason this `Connection` is closed, or `None` if the `Connection` is still open.
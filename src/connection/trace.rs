@@ -0,0 +1,55 @@
+use std::time::SystemTime;
+
+use crate::word::Word;
+
+/// The direction in which a traced line travels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// The line is sent by the client to the server.
+    Outgoing,
+    /// The line is received by the client from the server.
+    Incoming,
+}
+
+/// A single line sent or received by a `Connection`, as observed by a `Tracer`.
+#[derive(Debug, Clone, Copy)]
+pub struct TraceEvent<'a> {
+    /// The direction in which the line travels.
+    pub direction: Direction,
+    /// The time the line was sent or received.
+    pub timestamp: SystemTime,
+    /// The tag of the line, or `None` if the line has no tag, such as push messages.
+    pub tag: Option<&'a Word>,
+    /// The line, without tag and trailing newline.
+    ///
+    /// Passwords and API keys in outgoing lines are replaced by `<redacted>`.
+    pub line: &'a str,
+}
+
+/// Observes every line sent and received by a `Connection`.
+///
+/// A `Tracer` can be set using `Connection::set_tracer`, and is implemented for every
+/// `Fn(&TraceEvent<'_>)` closure:
+/// ```no_run
+/// use std::sync::Arc;
+/// use tomsg_rs::connection::*;
+///
+/// # async fn f(conn: Connection) {
+/// conn.set_tracer(Some(Arc::new(|event: &TraceEvent<'_>| {
+///     eprintln!("{:?} {:?} {}", event.direction, event.tag, event.line);
+/// })));
+/// # }
+/// ```
+pub trait Tracer: Send + Sync {
+    /// Called for every line sent or received.
+    fn trace(&self, event: &TraceEvent<'_>);
+}
+
+impl<F> Tracer for F
+where
+    F: Fn(&TraceEvent<'_>) + Send + Sync,
+{
+    fn trace(&self, event: &TraceEvent<'_>) {
+        self(event)
+    }
+}
@@ -0,0 +1,310 @@
+//! Holds a local echo of sent messages, which can be shown before the server confirms them.
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::command::Command;
+use crate::connection::{Connection, Error};
+use crate::id::Id;
+use crate::line::Line;
+use crate::message::Message;
+use crate::names::{RoomName, Username};
+use crate::pushmessage::PushMessage;
+use crate::reply::Reply;
+
+/// The delivery state of a `LocalMessage`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EchoState {
+    /// The message is queued, but not sent yet.
+    Pending,
+    /// The message is being sent, and the server hasn't replied yet.
+    Sent,
+    /// The server confirmed the message, which got the given ID.
+    Confirmed(Id),
+    /// Sending the message failed, it can be sent again using `LocalEcho::send`.
+    Failed,
+}
+
+/// A message sent by the user, as shown locally.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LocalMessage {
+    /// The name of the room the message is sent to.
+    pub roomname: Box<RoomName>,
+    /// The ID of the message the message replies on, if any.
+    pub reply_on: Option<Id>,
+    /// The contents of the message.
+    pub message: Box<Line>,
+    /// The time at which the message was queued, which may differ slightly from the timestamp
+    /// given by the server.
+    pub timestamp: SystemTime,
+    /// The delivery state of the message.
+    pub state: EchoState,
+}
+
+impl LocalMessage {
+    /// Returns this message as a `Message` sent by the user with the given `username`, if it is
+    /// confirmed.
+    #[must_use]
+    pub fn to_message(&self, username: &Username) -> Option<Message> {
+        match self.state {
+            EchoState::Confirmed(id) => Some(Message {
+                id,
+                reply_on: self.reply_on,
+                roomname: self.roomname.clone(),
+                username: username.to_owned(),
+                timestamp: self.timestamp,
+                message: self.message.clone(),
+            }),
+            EchoState::Pending | EchoState::Sent | EchoState::Failed => None,
+        }
+    }
+
+    /// Returns whether the given `message` has the room, reply and contents of this message.
+    fn matches(&self, message: &Message) -> bool {
+        self.roomname == message.roomname
+            && self.reply_on == message.reply_on
+            && self.message == message.message
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    next_key: u64,
+    messages: BTreeMap<u64, LocalMessage>,
+    /// Pushes held back by `LocalEcho::filter`, which may be of a message that is being sent.
+    held: Vec<Message>,
+    /// Held pushes that turned out not to be of a local message.
+    released: Vec<PushMessage>,
+}
+
+impl Inner {
+    /// Moves the held pushes that don't match any message that is being sent anymore to the
+    /// released pushes.
+    fn release(&mut self) {
+        let messages = &self.messages;
+        let (held, released): (Vec<_>, Vec<_>) = self.held.drain(..).partition(|pushed| {
+            messages
+                .values()
+                .any(|m| m.state == EchoState::Sent && m.matches(pushed))
+        });
+        self.held = held;
+        self.released
+            .extend(released.into_iter().map(PushMessage::Message));
+    }
+}
+
+/// Marks a message that is being sent as failed when dropped, so that it doesn't stay
+/// `EchoState::Sent` when sending is cancelled.
+struct Sending<'a> {
+    echo: &'a LocalEcho,
+    key: u64,
+}
+
+impl Drop for Sending<'_> {
+    fn drop(&mut self) {
+        let mut inner = self.echo.inner.lock().unwrap();
+        if let Some(message) = inner.messages.get_mut(&self.key) {
+            if message.state == EchoState::Sent {
+                message.state = EchoState::Failed;
+            }
+        }
+        inner.release();
+    }
+}
+
+/// The messages sent by the user, shown locally while they move from `EchoState::Pending`
+/// through `EchoState::Sent` to `EchoState::Confirmed` or `EchoState::Failed`.
+///
+/// A message is added using `LocalEcho::queue`, which returns a key identifying it, and sent
+/// using `LocalEcho::send`.
+/// The server may also push a sent message to the sessions of the user, possibly before
+/// replying to `Command::Send`; `LocalEcho::filter` drops such a push when the message is
+/// already shown locally.
+/// Pushes that are held back while a message is being sent, but turn out to be of another
+/// session of the user, are returned by `LocalEcho::released`.
+/// Confirmed messages should therefore be kept until their push can no longer arrive, after
+/// which they can be removed using `LocalEcho::remove`.
+///
+/// ```
+/// use std::convert::TryFrom;
+/// use tomsg_rs::echo::{EchoState, LocalEcho};
+/// use tomsg_rs::{Line, PushMessage, RoomName, Username};
+///
+/// let me = <&Username>::try_from("me").unwrap();
+/// let room = <&RoomName>::try_from("room").unwrap();
+/// let hi = <&Line>::try_from("hi").unwrap();
+///
+/// let echo = LocalEcho::new(me);
+/// let key = echo.queue(room, None, hi);
+/// assert_eq!(echo.get(key).unwrap().state, EchoState::Pending);
+///
+/// // the message isn't being sent, so the push is of a message sent by another session.
+/// let push = PushMessage::parse("_push message room me 0 7 -1 hi").unwrap().unwrap();
+/// assert!(echo.filter(push).is_some());
+/// ```
+#[derive(Debug)]
+pub struct LocalEcho {
+    username: Box<Username>,
+    inner: Mutex<Inner>,
+}
+
+impl LocalEcho {
+    /// Creates a new `LocalEcho` without any messages, for the user with the given `username`.
+    #[must_use]
+    pub fn new(username: &Username) -> Self {
+        Self {
+            username: username.to_owned(),
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    /// Adds a message to be sent to the room with the given `roomname` as
+    /// `EchoState::Pending`, so that it can be shown immediately.
+    ///
+    /// Returns the key of the message, which identifies it in this `LocalEcho`.
+    pub fn queue(&self, roomname: &RoomName, reply_on: Option<Id>, message: &Line) -> u64 {
+        let mut inner = self.inner.lock().unwrap();
+        let key = inner.next_key;
+        inner.next_key += 1;
+        inner.messages.insert(
+            key,
+            LocalMessage {
+                roomname: roomname.to_owned(),
+                reply_on,
+                message: message.to_owned(),
+                timestamp: SystemTime::now(),
+                state: EchoState::Pending,
+            },
+        );
+        key
+    }
+
+    /// Sends the message with the given `key` using `Command::Send`, marking it as
+    /// `EchoState::Sent` until the server replies, and then as `EchoState::Confirmed` with the
+    /// returned ID, or as `EchoState::Failed` if sending fails.
+    ///
+    /// A failed message can be sent again by calling this method again.
+    ///
+    /// # Panics
+    /// Panics if this `LocalEcho` doesn't contain a message with the given `key`, or if the
+    /// message is already being sent or confirmed.
+    pub async fn send(&self, conn: &Connection, key: u64) -> Result<Id, Error> {
+        let message = {
+            let mut inner = self.inner.lock().unwrap();
+            let message = inner.messages.get_mut(&key).expect("unknown local message");
+            match message.state {
+                EchoState::Pending | EchoState::Failed => message.state = EchoState::Sent,
+                EchoState::Sent | EchoState::Confirmed(_) => panic!("local message already sent"),
+            }
+            message.clone()
+        };
+        let sending = Sending { echo: self, key };
+
+        let command = Command::Send {
+            roomname: message.roomname.as_ref().into(),
+            reply_on: message.reply_on,
+            message: message.message.as_ref().into(),
+        };
+        let result = match conn.request(&command).await {
+            Ok(Reply::Number(id)) => {
+                Id::try_from(id).map_err(|_| Error::UnexpectedReply(Reply::Number(id)))
+            }
+            Ok(r) => Err(Error::UnexpectedReply(r)),
+            Err(e) => Err(e),
+        };
+
+        let mut inner = self.inner.lock().unwrap();
+        let state = match &result {
+            Ok(id) => EchoState::Confirmed(*id),
+            Err(_) => EchoState::Failed,
+        };
+        if let Some(message) = inner.messages.get_mut(&key) {
+            message.state = state;
+        }
+        // a held push with the returned ID is of this message, so it stays dropped.
+        if let Ok(id) = result {
+            inner.held.retain(|pushed| pushed.id != id);
+        }
+        inner.release();
+        drop(inner);
+        drop(sending);
+
+        result
+    }
+
+    /// Returns the message with the given `key`.
+    #[must_use]
+    pub fn get(&self, key: u64) -> Option<LocalMessage> {
+        self.inner.lock().unwrap().messages.get(&key).cloned()
+    }
+
+    /// Returns the keys and messages sent to the room with the given `roomname`, in the order
+    /// they were queued.
+    #[must_use]
+    pub fn messages(&self, roomname: &RoomName) -> Vec<(u64, LocalMessage)> {
+        self.inner
+            .lock()
+            .unwrap()
+            .messages
+            .iter()
+            .filter(|(_, message)| *message.roomname == *roomname)
+            .map(|(key, message)| (*key, message.clone()))
+            .collect()
+    }
+
+    /// Removes the message with the given `key`, for example when it is discarded after failing
+    /// or when it is shown as a regular message.
+    pub fn remove(&self, key: u64) -> Option<LocalMessage> {
+        self.inner.lock().unwrap().messages.remove(&key)
+    }
+
+    /// Passes the given `push` through this `LocalEcho`.
+    ///
+    /// Returns `None` if the `push` is a `PushMessage::Message` of a local message, since that
+    /// message is already shown.
+    /// This is the case if its ID is the ID of a confirmed local message.
+    /// A push that is sent by the user and matches the room, reply and contents of a message
+    /// that is being sent is held back as well, until the server replies with the ID of that
+    /// message; if the IDs differ the push is of another session of the user, and it is
+    /// returned by `LocalEcho::released`.
+    /// Other pushes are returned as is.
+    pub fn filter(&self, push: PushMessage) -> Option<PushMessage> {
+        let message = match &push {
+            PushMessage::Message(message) => message,
+            _ => return Some(push),
+        };
+
+        let mut inner = self.inner.lock().unwrap();
+        let confirmed = EchoState::Confirmed(message.id);
+        if inner.messages.values().any(|m| m.state == confirmed) {
+            return None;
+        }
+        if message.username != self.username {
+            return Some(push);
+        }
+
+        let sending = inner
+            .messages
+            .values()
+            .any(|m| m.state == EchoState::Sent && m.matches(message));
+        match push {
+            PushMessage::Message(message) if sending => {
+                inner.held.push(message);
+                None
+            }
+            push => Some(push),
+        }
+    }
+
+    /// Takes the pushes held back by `LocalEcho::filter` which turned out not to be of a local
+    /// message, in the order they were received.
+    ///
+    /// These should be handled like the pushes returned by `LocalEcho::filter`, and are
+    /// available after the `LocalEcho::send` call they were held for returns.
+    #[must_use]
+    pub fn released(&self) -> Vec<PushMessage> {
+        std::mem::take(&mut self.inner.lock().unwrap().released)
+    }
+}
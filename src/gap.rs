@@ -0,0 +1,185 @@
+//! Holds a tracker of the newest message seen in every room, which retrieves the messages that
+//! were missed while disconnected.
+
+use std::collections::BTreeMap;
+
+use futures_util::future::{self, Either};
+use futures_util::StreamExt;
+use tokio::sync::mpsc;
+
+use crate::connection::{Connection, Error};
+use crate::id::Id;
+use crate::message::Message;
+use crate::names::RoomName;
+use crate::pushmessage::PushMessage;
+
+/// Retrieves the messages in the room with the given `roomname` that are newer than `newest`,
+/// oldest first.
+async fn fetch_newer(
+    conn: &Connection,
+    roomname: &RoomName,
+    newest: Id,
+    page_size: i64,
+) -> Result<Vec<Message>, Error> {
+    let mut pages = Box::pin(conn.history_pages(roomname, page_size));
+    let mut messages = vec![];
+    while let Some(message) = pages.next().await {
+        let message = message?;
+        if message.id <= newest {
+            break;
+        }
+        messages.push(message);
+    }
+    messages.reverse();
+    Ok(messages)
+}
+
+/// The ID of the newest message seen in every room, used to fill the gap in the messages after
+/// reconnecting.
+///
+/// Every received `PushMessage` is passed through `GapTracker::filter`, which remembers the
+/// newest message of every room.
+/// After reconnecting, `GapTracker::catch_up` retrieves the messages sent while disconnected
+/// using `Command::History` and `Command::HistoryBefore`.
+///
+/// Only rooms of which a message was seen are caught up.
+///
+/// ```
+/// use std::convert::TryFrom;
+/// use tomsg_rs::gap::GapTracker;
+/// use tomsg_rs::{Id, PushMessage, RoomName};
+///
+/// let push = |id| {
+///     let line = format!("_push message room user 0 {} -1 hi", id);
+///     PushMessage::parse(&line).unwrap().unwrap()
+/// };
+///
+/// let mut tracker = GapTracker::new();
+/// assert!(tracker.filter(push(2)).is_some());
+///
+/// // messages that are not newer than the newest seen message are dropped.
+/// assert!(tracker.filter(push(2)).is_none());
+/// assert!(tracker.filter(push(1)).is_none());
+///
+/// let room = <&RoomName>::try_from("room").unwrap();
+/// assert_eq!(tracker.newest(room), Some(Id::try_from(2).unwrap()));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GapTracker {
+    newest: BTreeMap<Box<RoomName>, Id>,
+}
+
+impl GapTracker {
+    /// Creates a new `GapTracker` without any rooms.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remembers the given `message`, returns whether it is newer than the newest message seen
+    /// in its room.
+    pub fn observe(&mut self, message: &Message) -> bool {
+        match self.newest.get_mut(&*message.roomname) {
+            Some(newest) if *newest >= message.id => false,
+            Some(newest) => {
+                *newest = message.id;
+                true
+            }
+            None => {
+                self.newest.insert(message.roomname.clone(), message.id);
+                true
+            }
+        }
+    }
+
+    /// Passes the given `push` through this `GapTracker`.
+    ///
+    /// Returns `None` if the `push` is a `PushMessage::Message` which is not newer than the
+    /// newest message seen in its room, such as a message that was already retrieved by
+    /// `GapTracker::catch_up`.
+    /// Other pushes are returned as is.
+    pub fn filter(&mut self, push: PushMessage) -> Option<PushMessage> {
+        match &push {
+            PushMessage::Message(message) if !self.observe(message) => None,
+            _ => Some(push),
+        }
+    }
+
+    /// Stops tracking the room with the given `roomname`, for example after leaving it.
+    pub fn forget(&mut self, roomname: &RoomName) {
+        self.newest.remove(roomname);
+    }
+
+    /// Returns the ID of the newest message seen in the room with the given `roomname`.
+    #[must_use]
+    pub fn newest(&self, roomname: &RoomName) -> Option<Id> {
+        self.newest.get(roomname).copied()
+    }
+
+    /// Returns an iterator over the names of the tracked rooms.
+    pub fn rooms(&self) -> impl Iterator<Item = &RoomName> {
+        self.newest.keys().map(|r| &**r)
+    }
+
+    /// Retrieves the messages that are newer than the newest message seen in every tracked room,
+    /// in pages of `page_size` messages, on the newly connected `conn`.
+    ///
+    /// The pushes that are received on `pushes` in the meantime are buffered.
+    /// Returns the retrieved messages as `PushMessage::Message`, oldest first, followed by the
+    /// buffered pushes; every push is passed through `GapTracker::filter`, so no message is
+    /// returned twice.
+    /// Afterwards, live pushes can be received from `pushes` again.
+    ///
+    /// Rooms of which the server refuses to return the history, for example because the user
+    /// left the room, are forgotten.
+    ///
+    /// # Panics
+    /// Panics if `page_size` is not positive.
+    pub async fn catch_up(
+        &mut self,
+        conn: &Connection,
+        pushes: &mut mpsc::Receiver<PushMessage>,
+        page_size: i64,
+    ) -> Result<Vec<PushMessage>, Error> {
+        let rooms = self.newest.clone();
+        let mut fetch = Box::pin(async move {
+            let mut messages = vec![];
+            let mut refused = vec![];
+            for (roomname, newest) in rooms {
+                match fetch_newer(conn, &roomname, newest, page_size).await {
+                    Ok(m) => messages.extend(m),
+                    Err(Error::Server(_)) => refused.push(roomname),
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok((messages, refused))
+        });
+
+        // keep receiving pushes while waiting, so the connection doesn't block on a full channel.
+        let mut buffered = vec![];
+        let (mut messages, refused) = loop {
+            match future::select(fetch, Box::pin(pushes.recv())).await {
+                Either::Left((result, _)) => break result?,
+                Either::Right((Some(push), f)) => {
+                    buffered.push(push);
+                    fetch = f;
+                }
+                Either::Right((None, f)) => break f.await?,
+            }
+        };
+
+        for roomname in refused {
+            self.forget(&roomname);
+        }
+
+        // IDs increase over all rooms, so this orders the messages by the time they were sent.
+        messages.sort_by_key(|m| m.id);
+        let pushes = messages
+            .into_iter()
+            .map(PushMessage::Message)
+            .chain(buffered)
+            .filter_map(|push| self.filter(push))
+            .collect();
+        Ok(pushes)
+    }
+}
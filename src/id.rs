@@ -15,6 +15,11 @@ use std::fmt;
 /// assert!(Id::try_from(invalid).is_err());
 /// ```
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "i64", into = "i64")
+)]
 pub struct Id(i64);
 
 impl Id {
@@ -0,0 +1,147 @@
+//! Holds an interner for room names and usernames, to share them between many messages.
+//!
+//! A session only sees a limited amount of distinct room names and usernames, but every
+//! `Message` owns a copy of both.
+//! An `Interner` keeps a single `Arc` per distinct name, which `InternedMessage` instances share.
+//! This saves memory when holding many messages, and names can be compared by pointer using
+//! `Arc::ptr_eq`.
+
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time;
+
+use crate::id::Id;
+use crate::line::Line;
+use crate::message::{Message, MessageRef};
+use crate::names::{RoomName, Username};
+
+/// A table of interned names, which can be shared between threads.
+///
+/// ```
+/// use std::convert::TryFrom;
+/// use std::sync::Arc;
+/// use tomsg_rs::intern::Interner;
+/// use tomsg_rs::RoomName;
+///
+/// let interner = Interner::new();
+/// let a = interner.roomname(<&RoomName>::try_from("room").unwrap());
+/// let b = interner.roomname(<&RoomName>::try_from("room").unwrap());
+/// assert!(Arc::ptr_eq(&a, &b));
+/// ```
+#[derive(Debug, Default)]
+pub struct Interner {
+    roomnames: Mutex<HashSet<Arc<RoomName>>>,
+    usernames: Mutex<HashSet<Arc<Username>>>,
+}
+
+fn intern<T>(set: &Mutex<HashSet<Arc<T>>>, value: &T) -> Arc<T>
+where
+    T: ?Sized + Eq + Hash + ToOwned<Owned = Box<T>>,
+{
+    let mut set = set.lock().unwrap();
+    match set.get(value) {
+        Some(v) => v.clone(),
+        None => {
+            let v: Arc<T> = Arc::from(value.to_owned());
+            set.insert(v.clone());
+            v
+        }
+    }
+}
+
+impl Interner {
+    /// Creates a new, empty `Interner`.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the interned copy of the given `roomname`.
+    pub fn roomname(&self, roomname: &RoomName) -> Arc<RoomName> {
+        intern(&self.roomnames, roomname)
+    }
+
+    /// Returns the interned copy of the given `username`.
+    pub fn username(&self, username: &Username) -> Arc<Username> {
+        intern(&self.usernames, username)
+    }
+
+    /// Converts the given `message` into an `InternedMessage`, interning its room name and
+    /// username.
+    pub fn message<'a>(&self, message: impl Into<MessageRef<'a>>) -> InternedMessage {
+        let message = message.into();
+        InternedMessage {
+            id: message.id,
+            reply_on: message.reply_on,
+            roomname: self.roomname(message.roomname),
+            username: self.username(message.username),
+            timestamp: message.timestamp,
+            message: message.message.to_owned(),
+        }
+    }
+
+    /// Removes every name that is only referenced by this `Interner`.
+    pub fn purge(&self) {
+        self.roomnames
+            .lock()
+            .unwrap()
+            .retain(|r| Arc::strong_count(r) > 1);
+        self.usernames
+            .lock()
+            .unwrap()
+            .retain(|u| Arc::strong_count(u) > 1);
+    }
+
+    /// Returns the amount of distinct names in this `Interner`.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.roomnames.lock().unwrap().len() + self.usernames.lock().unwrap().len()
+    }
+
+    /// Returns whether this `Interner` holds no names.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A tomsg message in a room, sharing its room name and username with other messages.
+///
+/// An `InternedMessage` is created using `Interner::message`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InternedMessage {
+    /// The ID of the message.
+    pub id: Id,
+    /// The ID of the message this message replies on, if any.
+    pub reply_on: Option<Id>,
+    /// The name of the tomsg room this message is sent in.
+    pub roomname: Arc<RoomName>,
+    /// The username of the author of this message.
+    pub username: Arc<Username>,
+    /// The time this message was sent.
+    pub timestamp: time::SystemTime,
+    /// The contents of this message.
+    pub message: Box<Line>,
+}
+
+impl InternedMessage {
+    /// Converts this `InternedMessage` into a `Message`, copying the names.
+    #[must_use]
+    pub fn to_message(&self) -> Message {
+        MessageRef::from(self).into_owned()
+    }
+}
+
+impl<'a> From<&'a InternedMessage> for MessageRef<'a> {
+    fn from(message: &'a InternedMessage) -> Self {
+        Self {
+            id: message.id,
+            reply_on: message.reply_on,
+            roomname: &message.roomname,
+            username: &message.username,
+            timestamp: message.timestamp,
+            message: &message.message,
+        }
+    }
+}
@@ -1,9 +1,26 @@
+pub mod cache;
+pub mod codec;
 pub mod connection;
+pub mod echo;
+pub mod gap;
+pub mod intern;
+pub mod outbox;
+pub mod presence;
+pub mod split;
+pub mod state;
+#[cfg(feature = "sqlite")]
+pub mod store;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod thread;
+pub mod timestamp;
+pub mod unread;
 
 mod command;
 mod id;
 mod line;
 mod message;
+mod names;
 mod pushmessage;
 mod reply;
 mod util;
@@ -15,12 +32,13 @@ pub use connection::Connection;
 // structs
 pub use id::Id;
 pub use line::Line;
-pub use message::Message;
+pub use message::{Message, MessageRef};
+pub use names::{ApiKey, FirebaseToken, RoomName, Username};
 pub use word::Word;
 
 // enums
 pub use command::Command;
-pub use pushmessage::PushMessage;
+pub use pushmessage::{PushMessage, PushMessageRef};
 pub use reply::Reply;
 
 /*
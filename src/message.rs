@@ -1,22 +1,23 @@
-use std::convert::TryFrom;
-use std::convert::TryInto;
 use std::time;
 
 use crate::id::Id;
 use crate::line::Line;
-use crate::word::Word;
+use crate::names::{RoomName, Username};
+use crate::timestamp;
+use crate::util::{encode_optional_id, Words};
 
 /// A tomsg message message in a room.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Message {
     /// The ID of the message.
     pub id: Id,
     /// The ID of the message this message replies on, if any.
     pub reply_on: Option<Id>,
     /// The name of the tomsg room this message is sent in.
-    pub roomname: Box<Word>,
+    pub roomname: Box<RoomName>,
     /// The username of the author of this message.
-    pub username: Box<Word>,
+    pub username: Box<Username>,
     /// The time this message was sent.
     pub timestamp: time::SystemTime,
     /// The contents of this message.
@@ -24,31 +25,57 @@ pub struct Message {
 }
 
 impl Message {
-    pub(super) fn try_parse(words: &[&str]) -> Result<Self, String> {
-        let err = |field| format!("got invalid value for message field: {}", field);
-
-        macro_rules! parse {
-            ($val:expr, $type:ty, $field:expr) => {
-                $val.parse::<$type>().map_err(|_| err($field))?
-            };
-        }
-        let parse_id = |val, field| Id::try_from(val).map_err(|_| err(field));
-
-        let id = parse!(words[3], i64, "id");
-        let id = parse_id(id, "id")?;
+    /// Encodes this `Message` into the space separated fields used in replies and pushes.
+    ///
+    /// Fails if the timestamp can't be encoded.
+    pub(crate) fn encode(&self) -> Result<String, &'static str> {
+        Ok(format!(
+            "{} {} {} {} {} {}",
+            self.roomname,
+            self.username,
+            timestamp::to_micros(self.timestamp)?,
+            self.id,
+            encode_optional_id(self.reply_on),
+            self.message
+        ))
+    }
+}
 
-        let reply_on = match parse!(words[4], i64, "reply_on") {
-            -1 => None,
-            id => Some(parse_id(id, "reply_on")?),
-        };
-        let roomname = words[0].to_string().try_into()?;
-        let username = words[1].to_string().try_into()?;
+impl From<MessageRef<'_>> for Message {
+    fn from(message: MessageRef<'_>) -> Self {
+        message.into_owned()
+    }
+}
 
-        let timestamp = parse!(words[2], u64, "timestamp");
-        let timestamp = time::UNIX_EPOCH + time::Duration::from_micros(timestamp);
+/// A tomsg message in a room, borrowing its room name, username and contents.
+///
+/// This is the borrowed counterpart of `Message`, which can be parsed without allocating.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MessageRef<'a> {
+    /// The ID of the message.
+    pub id: Id,
+    /// The ID of the message this message replies on, if any.
+    pub reply_on: Option<Id>,
+    /// The name of the tomsg room this message is sent in.
+    pub roomname: &'a RoomName,
+    /// The username of the author of this message.
+    pub username: &'a Username,
+    /// The time this message was sent.
+    pub timestamp: time::SystemTime,
+    /// The contents of this message.
+    pub message: &'a Line,
+}
 
-        let message = words[5..].join(" ");
-        let message = message.try_into()?;
+impl<'a> MessageRef<'a> {
+    /// Parses the message fields at the front of `words`, this consumes the rest of the line.
+    pub(crate) fn parse(words: &mut Words<'a>) -> Result<Self, String> {
+        let roomname = words.word("roomname")?.into();
+        let username = words.word("username")?.into();
+        let timestamp = words.timestamp("timestamp")?;
+        let id = words.id("id")?;
+        let reply_on = words.optional_id("reply_on")?;
+        let message = words.rest("message")?;
 
         Ok(Self {
             id,
@@ -59,4 +86,30 @@ impl Message {
             message,
         })
     }
+
+    /// Converts this `MessageRef` into a `Message`, copying the borrowed data.
+    #[must_use]
+    pub fn into_owned(self) -> Message {
+        Message {
+            id: self.id,
+            reply_on: self.reply_on,
+            roomname: self.roomname.to_owned(),
+            username: self.username.to_owned(),
+            timestamp: self.timestamp,
+            message: self.message.to_owned(),
+        }
+    }
+}
+
+impl<'a> From<&'a Message> for MessageRef<'a> {
+    fn from(message: &'a Message) -> Self {
+        Self {
+            id: message.id,
+            reply_on: message.reply_on,
+            roomname: &message.roomname,
+            username: &message.username,
+            timestamp: message.timestamp,
+            message: &message.message,
+        }
+    }
 }
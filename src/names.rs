@@ -0,0 +1,190 @@
+use std::borrow::{Borrow, Cow};
+use std::convert::TryFrom;
+use std::fmt;
+use std::mem;
+use std::ops::Deref;
+
+use crate::word::Word;
+
+/// Defines a newtype around `Word`, which is used for a specific kind of value so that values of
+/// different kinds can't be swapped by accident.
+macro_rules! word_newtype {
+    ($(#[$meta:meta])* $name:ident) => {
+        $(#[$meta])*
+        #[repr(transparent)]
+        #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(Word);
+
+        impl $name {
+            #[doc = concat!("Create a `", stringify!($name), "` from the given `word`.")]
+            #[must_use]
+            pub fn from_word(word: &Word) -> &Self {
+                unsafe { mem::transmute(word) }
+            }
+
+            #[doc = concat!("Create a `", stringify!($name), "` from the given boxed `word`.")]
+            #[must_use]
+            pub fn from_boxed_word(word: Box<Word>) -> Box<Self> {
+                unsafe { mem::transmute(word) }
+            }
+
+            #[doc = concat!("Returns the `Word` this `", stringify!($name), "` consists of.")]
+            #[must_use]
+            pub fn as_word(&self) -> &Word {
+                &self.0
+            }
+
+            #[doc = concat!("Converts this `Box<", stringify!($name), ">` into a `Box<Word>`.")]
+            #[must_use]
+            pub fn into_boxed_word(self: Box<Self>) -> Box<Word> {
+                unsafe { mem::transmute(self) }
+            }
+
+            #[doc = concat!("Extracts a string slice containing the contents of the `", stringify!($name), "`.")]
+            #[must_use]
+            pub fn as_str(&self) -> &str {
+                self.0.as_str()
+            }
+
+            #[doc = concat!("Converts this `Box<", stringify!($name), ">` into a `String`.")]
+            #[must_use]
+            pub fn into_string(self: Box<Self>) -> String {
+                self.into_boxed_word().into_string()
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}", &self.0)
+            }
+        }
+
+        impl<'a> From<&'a Word> for &'a $name {
+            fn from(word: &'a Word) -> Self {
+                $name::from_word(word)
+            }
+        }
+        impl From<Box<Word>> for Box<$name> {
+            fn from(word: Box<Word>) -> Self {
+                $name::from_boxed_word(word)
+            }
+        }
+
+        impl From<Box<$name>> for Cow<'_, $name> {
+            fn from(name: Box<$name>) -> Self {
+                Self::Owned(name)
+            }
+        }
+        impl<'a> From<&'a $name> for Cow<'a, $name> {
+            fn from(name: &'a $name) -> Self {
+                Self::Borrowed(name)
+            }
+        }
+
+        impl TryFrom<String> for Box<$name> {
+            type Error = &'static str;
+
+            fn try_from(val: String) -> Result<Self, Self::Error> {
+                Box::<Word>::try_from(val).map($name::from_boxed_word)
+            }
+        }
+
+        impl<'a> TryFrom<&'a str> for &'a $name {
+            type Error = &'static str;
+
+            fn try_from(val: &'a str) -> Result<Self, Self::Error> {
+                <&Word>::try_from(val).map($name::from_word)
+            }
+        }
+
+        impl Borrow<str> for $name {
+            fn borrow(&self) -> &str {
+                self.as_str()
+            }
+        }
+
+        impl Deref for $name {
+            type Target = Word;
+
+            fn deref(&self) -> &Self::Target {
+                &self.0
+            }
+        }
+
+        impl ToOwned for $name {
+            type Owned = Box<$name>;
+
+            fn to_owned(&self) -> Self::Owned {
+                $name::from_boxed_word(self.0.to_owned())
+            }
+        }
+
+        impl Clone for Box<$name> {
+            fn clone(&self) -> Self {
+                (**self).to_owned()
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl serde::Serialize for $name {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                self.0.serialize(serializer)
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl<'de> serde::Deserialize<'de> for Box<$name> {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                Box::<Word>::deserialize(deserializer).map($name::from_boxed_word)
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl<'de: 'a, 'a> serde::Deserialize<'de> for &'a $name {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                <&Word>::deserialize(deserializer).map($name::from_word)
+            }
+        }
+    };
+}
+
+word_newtype! {
+    /// A `RoomName` is a `Word` which is the name of a tomsg room.
+    ///
+    /// You can obtain a `RoomName` by calling `try_from`, or by converting a `Word`:
+    /// ```
+    /// use tomsg_rs::{RoomName, Word};
+    /// use std::convert::TryFrom;
+    ///
+    /// let roomname = <&RoomName>::try_from("room").unwrap();
+    /// assert!(<&RoomName>::try_from("not a room").is_err());
+    ///
+    /// let word = <&Word>::try_from("room").unwrap();
+    /// assert_eq!(RoomName::from_word(word), roomname);
+    /// ```
+    RoomName
+}
+
+word_newtype! {
+    /// A `Username` is a `Word` which is the name of a tomsg user.
+    ///
+    /// You can obtain a `Username` by calling `try_from`, or by converting a `Word`:
+    /// ```
+    /// use tomsg_rs::Username;
+    /// use std::convert::TryFrom;
+    ///
+    /// assert!(<&Username>::try_from("user").is_ok());
+    /// assert!(<&Username>::try_from("not a user").is_err());
+    /// ```
+    Username
+}
+
+word_newtype! {
+    /// An `ApiKey` is a `Word` which is used to authenticate `Command::SendAt`.
+    ApiKey
+}
+
+word_newtype! {
+    /// A `FirebaseToken` is a `Word` which identifies a device to send push notifications to.
+    FirebaseToken
+}
@@ -0,0 +1,460 @@
+//! Holds a persistent queue of outgoing messages, which are sent when connected and replayed
+//! after reconnecting.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::TryFrom;
+use std::error;
+use std::fmt::{self, Write as _};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use futures_util::StreamExt;
+
+use crate::command::Command;
+use crate::connection::{self, Connection};
+use crate::id::Id;
+use crate::line::Line;
+use crate::names::{RoomName, Username};
+use crate::reply::Reply;
+use crate::util::{invalid_data, write_atomically};
+
+/// An error that occured while flushing an `Outbox`.
+#[derive(Debug)]
+pub enum Error {
+    /// Persisting the outbox failed.
+    Io(io::Error),
+    /// Sending a message failed.
+    Connection(connection::Error),
+    /// The server refused the message with the given key with the given error.
+    ///
+    /// The message stays pending, so it is sent again by the next `Outbox::flush`, for example
+    /// after logging in again; it can be dropped using `Outbox::remove`.
+    Refused { key: u64, error: Box<Line> },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Connection(e) => write!(f, "{}", e),
+            Error::Refused { key, error } => write!(f, "message {} refused: {}", key, error),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Connection(e) => Some(e),
+            Error::Refused { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<connection::Error> for Error {
+    fn from(e: connection::Error) -> Self {
+        Error::Connection(e)
+    }
+}
+
+/// Returns the ID of the newest message in the room with the given `roomname`.
+async fn newest_message(
+    conn: &Connection,
+    roomname: &RoomName,
+) -> Result<Option<Id>, connection::Error> {
+    let command = Command::History {
+        roomname: roomname.into(),
+        count: 1,
+    };
+    match conn.request(&command).await? {
+        Reply::History(messages) => Ok(messages.iter().map(|m| m.id).max()),
+        r => Err(connection::Error::UnexpectedReply(r)),
+    }
+}
+
+/// The delivery state of a message in an `Outbox`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum OutboxState {
+    /// The message hasn't been sent yet.
+    Pending,
+    /// The message has been sent, but the server hasn't confirmed it yet.
+    Sent,
+    /// The server confirmed the message, which got the given ID.
+    Confirmed(Id),
+}
+
+/// A message in an `Outbox`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OutboxEntry {
+    roomname: Box<RoomName>,
+    reply_on: Option<Id>,
+    message: Box<Line>,
+    state: OutboxState,
+    /// The newest message in the room at the moment this message was sent, or `None` if the
+    /// room was empty; this message can only be found after it in the history.
+    after: Option<Id>,
+}
+
+impl OutboxEntry {
+    /// The name of the room the message is sent to.
+    #[must_use]
+    pub fn roomname(&self) -> &RoomName {
+        &self.roomname
+    }
+
+    /// The ID of the message the message replies on, if any.
+    #[must_use]
+    pub fn reply_on(&self) -> Option<Id> {
+        self.reply_on
+    }
+
+    /// The contents of the message.
+    #[must_use]
+    pub fn message(&self) -> &Line {
+        &self.message
+    }
+
+    /// The delivery state of the message.
+    #[must_use]
+    pub fn state(&self) -> &OutboxState {
+        &self.state
+    }
+
+    /// Returns whether the message is confirmed by the server.
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        matches!(self.state, OutboxState::Confirmed(_))
+    }
+}
+
+/// A queue of outgoing messages, persisted to a file.
+///
+/// Messages are added using `Outbox::queue`, which doesn't need a connection, and sent in order
+/// using `Outbox::flush`.
+/// If the connection closes while flushing, the messages that are not confirmed by the server
+/// remain in the outbox, and are sent by the next `Outbox::flush` after reconnecting.
+/// A message of which it is unknown whether the server received it is looked up in the history
+/// of its room first, so that it is never sent twice.
+///
+/// Only messages that are not confirmed are persisted; confirmed messages are kept in memory
+/// until they are removed using `Outbox::take_finished`.
+///
+/// ```
+/// use std::convert::TryFrom;
+/// use tomsg_rs::outbox::{Outbox, OutboxState};
+/// use tomsg_rs::{Line, RoomName, Username};
+///
+/// let me = <&Username>::try_from("me").unwrap();
+/// let room = <&RoomName>::try_from("room").unwrap();
+/// let path = std::env::temp_dir().join(format!("tomsg-outbox-{}", std::process::id()));
+///
+/// let mut outbox = Outbox::open(&path, me).unwrap();
+/// let key = outbox.queue(room, None, <&Line>::try_from("hi").unwrap()).unwrap();
+/// drop(outbox);
+///
+/// // the message survives restarts, until it is flushed.
+/// let outbox = Outbox::open(&path, me).unwrap();
+/// assert_eq!(outbox.get(key).unwrap().state(), &OutboxState::Pending);
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Outbox {
+    path: PathBuf,
+    username: Box<Username>,
+    next_key: u64,
+    entries: BTreeMap<u64, OutboxEntry>,
+}
+
+impl Outbox {
+    /// Opens the `Outbox` of the user with the given `username`, persisted in the file at
+    /// `path`.
+    ///
+    /// If the file doesn't exist, an empty `Outbox` is created.
+    /// Fails with `io::ErrorKind::InvalidData` if the file is malformed.
+    pub fn open(path: impl AsRef<Path>, username: &Username) -> io::Result<Self> {
+        let mut outbox = Self {
+            path: path.as_ref().to_owned(),
+            username: username.to_owned(),
+            next_key: 0,
+            entries: BTreeMap::new(),
+        };
+
+        let contents = match fs::read_to_string(&outbox.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(outbox),
+            Err(e) => return Err(e),
+        };
+
+        let parse_id = |s: &str| -> io::Result<Option<Id>> {
+            if s == "-" {
+                return Ok(None);
+            }
+            let id: i64 = s.parse().map_err(invalid_data)?;
+            Id::try_from(id).map(Some).map_err(invalid_data)
+        };
+
+        // a message may end in a carriage return, which `str::lines` would strip.
+        let mut lines = contents.split('\n').filter(|line| !line.is_empty());
+        outbox.next_key = lines
+            .next()
+            .unwrap_or_default()
+            .parse()
+            .map_err(invalid_data)?;
+
+        for line in lines {
+            let mut words = line.splitn(6, ' ');
+            let mut word = || words.next().ok_or_else(|| invalid_data("missing field"));
+            let key = word()?.parse().map_err(invalid_data)?;
+            let state = match word()? {
+                "pending" => OutboxState::Pending,
+                "sent" => OutboxState::Sent,
+                _ => return Err(invalid_data("invalid state")),
+            };
+            let after = parse_id(word()?)?;
+            let roomname = Box::<RoomName>::try_from(word()?.to_owned()).map_err(invalid_data)?;
+            let reply_on = parse_id(word()?)?;
+            let message = Box::<Line>::try_from(word()?.to_owned()).map_err(invalid_data)?;
+
+            outbox.entries.insert(
+                key,
+                OutboxEntry {
+                    roomname,
+                    reply_on,
+                    message,
+                    state,
+                    after,
+                },
+            );
+        }
+
+        Ok(outbox)
+    }
+
+    /// Writes the unfinished messages to the file of this `Outbox`, replacing it atomically.
+    fn save(&self) -> io::Result<()> {
+        let id = |id: Option<Id>| id.map_or_else(|| String::from("-"), |id| id.to_string());
+
+        let mut contents = format!("{}\n", self.next_key);
+        for (key, entry) in &self.entries {
+            let state = match entry.state {
+                OutboxState::Pending => "pending",
+                OutboxState::Sent => "sent",
+                OutboxState::Confirmed(_) => continue,
+            };
+            writeln!(
+                contents,
+                "{} {} {} {} {} {}",
+                key,
+                state,
+                id(entry.after),
+                entry.roomname,
+                id(entry.reply_on),
+                entry.message
+            )
+            .unwrap();
+        }
+
+        write_atomically(&self.path, &contents)
+    }
+
+    /// Adds a message to this `Outbox`, to be sent to the room with the given `roomname` by the
+    /// next `Outbox::flush`.
+    ///
+    /// Returns the key of the message, which identifies it in this `Outbox`.
+    pub fn queue(
+        &mut self,
+        roomname: &RoomName,
+        reply_on: Option<Id>,
+        message: &Line,
+    ) -> io::Result<u64> {
+        let key = self.next_key;
+        self.next_key += 1;
+        self.entries.insert(
+            key,
+            OutboxEntry {
+                roomname: roomname.to_owned(),
+                reply_on,
+                message: message.to_owned(),
+                state: OutboxState::Pending,
+                after: None,
+            },
+        );
+        self.save()?;
+        Ok(key)
+    }
+
+    /// Returns the message with the given `key`.
+    #[must_use]
+    pub fn get(&self, key: u64) -> Option<&OutboxEntry> {
+        self.entries.get(&key)
+    }
+
+    /// Returns an iterator over the keys and messages in this `Outbox`, in the order they were
+    /// queued.
+    pub fn entries(&self) -> impl Iterator<Item = (u64, &OutboxEntry)> {
+        self.entries.iter().map(|(key, entry)| (*key, entry))
+    }
+
+    /// Returns the amount of messages that are not confirmed yet.
+    #[must_use]
+    pub fn unfinished(&self) -> usize {
+        self.entries.values().filter(|e| !e.is_finished()).count()
+    }
+
+    /// Removes the confirmed messages from this `Outbox`, returning them with their keys.
+    pub fn take_finished(&mut self) -> Vec<(u64, OutboxEntry)> {
+        let keys: Vec<u64> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.is_finished())
+            .map(|(key, _)| *key)
+            .collect();
+        keys.into_iter()
+            .map(|key| (key, self.entries.remove(&key).unwrap()))
+            .collect()
+    }
+
+    fn set_state(&mut self, key: u64, state: OutboxState) {
+        self.entries.get_mut(&key).unwrap().state = state;
+    }
+
+    /// Removes the message with the given `key`, for example a message refused by the server
+    /// that shouldn't be sent again.
+    pub fn remove(&mut self, key: u64) -> io::Result<Option<OutboxEntry>> {
+        let entry = self.entries.remove(&key);
+        if entry.is_some() {
+            self.save()?;
+        }
+        Ok(entry)
+    }
+
+    /// Looks up the message with the given `key`, of which it is unknown whether the server
+    /// received it, in the history of its room.
+    async fn find_sent(
+        &self,
+        conn: &Connection,
+        key: u64,
+    ) -> Result<Option<Id>, connection::Error> {
+        let entry = &self.entries[&key];
+        let claimed = |id: Id| {
+            self.entries
+                .values()
+                .any(|e| e.state == OutboxState::Confirmed(id))
+        };
+
+        let mut history = Box::pin(conn.history_pages(&entry.roomname, 50));
+        while let Some(message) = history.next().await {
+            let message = message?;
+            if entry.after.is_some_and(|after| message.id <= after) {
+                break;
+            }
+            if message.username == self.username
+                && message.reply_on == entry.reply_on
+                && message.message == entry.message
+                && !claimed(message.id)
+            {
+                return Ok(Some(message.id));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Sends every message that isn't confirmed yet on `conn`, in the order they were queued.
+    ///
+    /// Messages that were sent before, but of which the confirmation wasn't received, are only
+    /// sent again if they can't be found in the history of their room.
+    /// If the history can't be retrieved, for example because the user left the room, such a
+    /// message stays `OutboxState::Sent` and the later messages to its room are not sent.
+    ///
+    /// If sending a message fails, the remaining messages are kept for the next call.
+    /// A message refused by the server results in `Error::Refused`.
+    pub async fn flush(&mut self, conn: &Connection) -> Result<(), Error> {
+        let in_flight: Vec<u64> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.state == OutboxState::Sent)
+            .map(|(key, _)| *key)
+            .collect();
+        let mut unresolved = BTreeSet::new();
+        for key in in_flight {
+            let state = match self.find_sent(conn, key).await {
+                Ok(Some(id)) => OutboxState::Confirmed(id),
+                Ok(None) => OutboxState::Pending,
+                Err(connection::Error::Server(_)) => {
+                    unresolved.insert(self.entries[&key].roomname.clone());
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            self.set_state(key, state);
+            self.save()?;
+        }
+
+        let pending: Vec<u64> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| {
+                entry.state == OutboxState::Pending && !unresolved.contains(&entry.roomname)
+            })
+            .map(|(key, _)| *key)
+            .collect();
+
+        // the newest message in every room, which the next message sent to it will follow.
+        let mut newest: HashMap<Box<RoomName>, Option<Id>> = HashMap::new();
+        for key in pending {
+            let roomname = self.entries[&key].roomname.clone();
+            let after = match newest.get(&roomname) {
+                Some(after) => *after,
+                None => match newest_message(conn, &roomname).await {
+                    Ok(after) => after,
+                    Err(connection::Error::Server(error)) => {
+                        return Err(Error::Refused { key, error })
+                    }
+                    Err(e) => return Err(e.into()),
+                },
+            };
+
+            let entry = self.entries.get_mut(&key).unwrap();
+            entry.state = OutboxState::Sent;
+            entry.after = after;
+            self.save()?;
+
+            let entry = &self.entries[&key];
+            let command = Command::Send {
+                roomname: entry.roomname.as_ref().into(),
+                reply_on: entry.reply_on,
+                message: entry.message.as_ref().into(),
+            };
+            let id = match conn.request(&command).await {
+                Ok(Reply::Number(id)) => match Id::try_from(id) {
+                    Ok(id) => id,
+                    Err(_) => {
+                        let r = Reply::Number(id);
+                        return Err(connection::Error::UnexpectedReply(r).into());
+                    }
+                },
+                Ok(r) => return Err(connection::Error::UnexpectedReply(r).into()),
+                Err(connection::Error::Server(error)) => {
+                    // the server didn't store the message, so it can safely be sent again.
+                    self.set_state(key, OutboxState::Pending);
+                    self.save()?;
+                    return Err(Error::Refused { key, error });
+                }
+                Err(e) => return Err(e.into()),
+            };
+            self.set_state(key, OutboxState::Confirmed(id));
+            self.save()?;
+            newest.insert(roomname, Some(id));
+        }
+
+        Ok(())
+    }
+}
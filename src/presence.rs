@@ -0,0 +1,187 @@
+//! Holds a tracker of the online state of users, driven by `PushMessage::Online`.
+
+use std::collections::{BTreeSet, HashMap};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+
+use futures_core::Stream;
+use tokio::sync::mpsc;
+
+use crate::command::Command;
+use crate::connection::{Connection, Error};
+use crate::names::Username;
+use crate::pushmessage::PushMessage;
+use crate::reply::Reply;
+use crate::state::ClientState;
+
+/// The known online state of a single user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UserPresence {
+    /// The amount of sessions of the user that are currently online.
+    pub sessions: i64,
+    /// The last time the user was known to be online, or `None` if the user hasn't been seen
+    /// online.
+    pub last_seen: Option<SystemTime>,
+}
+
+impl UserPresence {
+    /// Returns whether the user has any online sessions.
+    #[must_use]
+    pub fn is_online(&self) -> bool {
+        self.sessions > 0
+    }
+}
+
+/// A user going online or offline.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PresenceChange {
+    /// The username of the user.
+    pub username: Box<Username>,
+    /// Whether the user went online, if `false` the user went offline.
+    pub online: bool,
+    /// The time at which the change was observed.
+    pub timestamp: SystemTime,
+}
+
+/// A `Stream` of `PresenceChange` instances, created using `Presence::changes`.
+///
+/// The stream ends when the `Presence` it belongs to is dropped.
+#[derive(Debug)]
+pub struct PresenceChanges {
+    receiver: mpsc::UnboundedReceiver<PresenceChange>,
+}
+
+impl Stream for PresenceChanges {
+    type Item = PresenceChange;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+/// The online state of users, kept up to date by passing every received `PushMessage` to
+/// `Presence::apply`.
+///
+/// Users that are not known are considered offline.
+///
+/// ```
+/// use std::convert::TryFrom;
+/// use tomsg_rs::presence::Presence;
+/// use tomsg_rs::{PushMessage, Username};
+///
+/// let user = <&Username>::try_from("user").unwrap();
+///
+/// let mut presence = Presence::new();
+/// presence.apply(&PushMessage::Online {
+///     sessions: 2,
+///     username: user.to_owned(),
+/// });
+/// assert!(presence.is_online(user));
+///
+/// presence.apply(&PushMessage::Online {
+///     sessions: 0,
+///     username: user.to_owned(),
+/// });
+/// assert!(!presence.is_online(user));
+/// assert!(presence.get(user).unwrap().last_seen.is_some());
+/// ```
+#[derive(Debug, Default)]
+pub struct Presence {
+    users: HashMap<Box<Username>, UserPresence>,
+    subscribers: Vec<mpsc::UnboundedSender<PresenceChange>>,
+}
+
+impl Presence {
+    /// Creates a new `Presence` without any known users.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Retrieves the online state of every member of the rooms in `state` using
+    /// `Command::IsOnline`.
+    pub async fn seed(&mut self, conn: &Connection, state: &ClientState) -> Result<(), Error> {
+        let usernames: BTreeSet<&Username> = state
+            .rooms()
+            .filter_map(|roomname| state.members(roomname))
+            .flatten()
+            .collect();
+
+        for username in usernames {
+            let command = Command::IsOnline {
+                username: username.into(),
+            };
+            match conn.request(&command).await? {
+                Reply::Number(sessions) => self.update(username, sessions, SystemTime::now()),
+                r => return Err(Error::UnexpectedReply(r)),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Updates this `Presence` using the given `push`.
+    ///
+    /// Pushes other than `PushMessage::Online` are ignored.
+    pub fn apply(&mut self, push: &PushMessage) {
+        if let PushMessage::Online { sessions, username } = push {
+            self.update(username, *sessions, SystemTime::now());
+        }
+    }
+
+    /// Sets the amount of online sessions of the user with the given `username`, as observed at
+    /// `timestamp`.
+    pub fn update(&mut self, username: &Username, sessions: i64, timestamp: SystemTime) {
+        let presence = self
+            .users
+            .entry(username.to_owned())
+            .or_insert(UserPresence {
+                sessions: 0,
+                last_seen: None,
+            });
+
+        let was_online = presence.is_online();
+        presence.sessions = sessions;
+        if was_online || presence.is_online() {
+            presence.last_seen = Some(timestamp);
+        }
+
+        if was_online != presence.is_online() {
+            let change = PresenceChange {
+                username: username.to_owned(),
+                online: presence.is_online(),
+                timestamp,
+            };
+            self.subscribers
+                .retain(|subscriber| subscriber.send(change.clone()).is_ok());
+        }
+    }
+
+    /// Returns a `Stream` of every future change in the online state of a user.
+    pub fn changes(&mut self) -> PresenceChanges {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.subscribers.push(sender);
+        PresenceChanges { receiver }
+    }
+
+    /// Returns the known online state of the user with the given `username`.
+    #[must_use]
+    pub fn get(&self, username: &Username) -> Option<&UserPresence> {
+        self.users.get(username)
+    }
+
+    /// Returns whether the user with the given `username` is known to be online.
+    #[must_use]
+    pub fn is_online(&self, username: &Username) -> bool {
+        self.get(username).is_some_and(UserPresence::is_online)
+    }
+
+    /// Returns an iterator over the usernames of every user that is known to be online.
+    pub fn online(&self) -> impl Iterator<Item = &Username> {
+        self.users
+            .iter()
+            .filter(|(_, presence)| presence.is_online())
+            .map(|(username, _)| &**username)
+    }
+}
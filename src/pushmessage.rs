@@ -1,16 +1,17 @@
-use crate::message::Message;
-use crate::util::{expect_word, parsei64};
-use crate::word::Word;
+use crate::message::{Message, MessageRef};
+use crate::names::{RoomName, Username};
+use crate::util::Words;
 
 /// An item pushed from the tomsg server to the client.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PushMessage {
     /// An update to the online state of a person that you participate with in a room.
     Online {
         /// The amount of sessions currently marked as online.
         sessions: i64,
         /// The username of the user.
-        username: Box<Word>,
+        username: Box<Username>,
     },
     /// A new message is sent in a room that the client participates in.
     Message(Message),
@@ -20,16 +21,16 @@ pub enum PushMessage {
     /// another session of the logged-in user joined the room with name `roomname`.
     Invite {
         /// The name of the room the client is invited in.
-        roomname: Box<Word>,
+        roomname: Box<RoomName>,
         /// The username of the user that invited the client.
-        inviter: Box<Word>,
+        inviter: Box<Username>,
     },
     /// A person has joined a room you participate in.
     Join {
         /// The room in question.
-        roomname: Box<Word>,
+        roomname: Box<RoomName>,
         /// The username of the user that joined the room.
-        username: Box<Word>,
+        username: Box<Username>,
     },
     /// A person has left a room you participate in.
     ///
@@ -37,44 +38,155 @@ pub enum PushMessage {
     /// another session of the logged-in user left the room with name `roomname`.
     Leave {
         /// The room in question.
-        roomname: Box<Word>,
+        roomname: Box<RoomName>,
         /// The username of the user that left the room.
-        username: Box<Word>,
+        username: Box<Username>,
     },
 }
 
 impl PushMessage {
-    pub(super) fn parse(s: &str) -> Option<Self> {
-        let words: Vec<_> = s.split(' ').collect();
-        assert!(words[0] == "_push");
-        let item = match words[1] {
+    /// Encodes this `PushMessage` into a push line, without trailing newline.
+    ///
+    /// Fails if the push contains a `Message` of which the timestamp can't be encoded.
+    pub fn encode(&self) -> Result<String, &'static str> {
+        let res = match self {
+            Self::Online { sessions, username } => {
+                format!("_push online {} {}", sessions, username)
+            }
+            Self::Message(message) => format!("_push message {}", message.encode()?),
+            Self::Invite { roomname, inviter } => format!("_push invite {} {}", roomname, inviter),
+            Self::Join { roomname, username } => format!("_push join {} {}", roomname, username),
+            Self::Leave { roomname, username } => format!("_push leave {} {}", roomname, username),
+        };
+        Ok(res)
+    }
+
+    /// Parses the given push `line`, without trailing newline.
+    ///
+    /// Returns `Ok(None)` for pushes that carry no information, such as the server pinging the
+    /// client.
+    pub fn parse(line: &str) -> Result<Option<Self>, String> {
+        PushMessageRef::parse(line).map(|p| p.map(PushMessageRef::into_owned))
+    }
+}
+
+/// An item pushed from the tomsg server to the client, borrowing its data.
+///
+/// This is the borrowed counterpart of `PushMessage`, which can be parsed without allocating.
+///
+/// ```
+/// use tomsg_rs::PushMessageRef;
+///
+/// let line = "_push join room user";
+/// match PushMessageRef::parse(line).unwrap() {
+///     Some(PushMessageRef::Join { roomname, username }) => {
+///         assert_eq!(roomname.as_str(), "room");
+///         assert_eq!(username.as_str(), "user");
+///     }
+///     _ => unreachable!(),
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum PushMessageRef<'a> {
+    /// An update to the online state of a person that you participate with in a room.
+    Online {
+        /// The amount of sessions currently marked as online.
+        sessions: i64,
+        /// The username of the user.
+        username: &'a Username,
+    },
+    /// A new message is sent in a room that the client participates in.
+    Message(MessageRef<'a>),
+    /// A person invited the current client to a room.
+    Invite {
+        /// The name of the room the client is invited in.
+        roomname: &'a RoomName,
+        /// The username of the user that invited the client.
+        inviter: &'a Username,
+    },
+    /// A person has joined a room you participate in.
+    Join {
+        /// The room in question.
+        roomname: &'a RoomName,
+        /// The username of the user that joined the room.
+        username: &'a Username,
+    },
+    /// A person has left a room you participate in.
+    Leave {
+        /// The room in question.
+        roomname: &'a RoomName,
+        /// The username of the user that left the room.
+        username: &'a Username,
+    },
+}
+
+impl<'a> PushMessageRef<'a> {
+    /// Parses the given push `line`, without trailing newline, borrowing from it.
+    ///
+    /// Returns `Ok(None)` for pushes that carry no information, such as the server pinging the
+    /// client.
+    pub fn parse(line: &'a str) -> Result<Option<Self>, String> {
+        let mut words = Words::new(line);
+
+        if words.word("push")?.as_str() != "_push" {
+            return Err(String::from("not a push message"));
+        }
+        let item = match words.word("type")?.as_str() {
             "online" => Self::Online {
-                sessions: parsei64(words[2]),
-                username: expect_word(words[3]),
+                sessions: words.number("sessions")?,
+                username: words.word("username")?.into(),
             },
-            "message" => {
-                let message = Message::try_parse(&words[2..]).unwrap();
-                Self::Message(message)
-            }
+            "message" => Self::Message(MessageRef::parse(&mut words)?),
             "invite" => Self::Invite {
-                roomname: expect_word(words[2]),
-                inviter: expect_word(words[3]),
+                roomname: words.word("roomname")?.into(),
+                inviter: words.word("inviter")?.into(),
             },
             "join" => Self::Join {
-                roomname: expect_word(words[2]),
-                username: expect_word(words[3]),
+                roomname: words.word("roomname")?.into(),
+                username: words.word("username")?.into(),
             },
             "leave" => Self::Leave {
-                roomname: expect_word(words[2]),
-                username: expect_word(words[3]),
+                roomname: words.word("roomname")?.into(),
+                username: words.word("username")?.into(),
             },
 
             // we can ignore this
-            "ping" => return None,
+            "ping" => return Ok(None),
 
-            _ => panic!("unknown push type"),
+            w => return Err(format!("unknown push type: '{}'", w)),
         };
 
-        Some(item)
+        Ok(Some(item))
+    }
+
+    /// Converts this `PushMessageRef` into a `PushMessage`, copying the borrowed data.
+    #[must_use]
+    pub fn into_owned(self) -> PushMessage {
+        match self {
+            Self::Online { sessions, username } => PushMessage::Online {
+                sessions,
+                username: username.to_owned(),
+            },
+            Self::Message(message) => PushMessage::Message(message.into_owned()),
+            Self::Invite { roomname, inviter } => PushMessage::Invite {
+                roomname: roomname.to_owned(),
+                inviter: inviter.to_owned(),
+            },
+            Self::Join { roomname, username } => PushMessage::Join {
+                roomname: roomname.to_owned(),
+                username: username.to_owned(),
+            },
+            Self::Leave { roomname, username } => PushMessage::Leave {
+                roomname: roomname.to_owned(),
+                username: username.to_owned(),
+            },
+        }
+    }
+}
+
+impl From<PushMessageRef<'_>> for PushMessage {
+    fn from(push: PushMessageRef<'_>) -> Self {
+        push.into_owned()
     }
 }
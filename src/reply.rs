@@ -1,9 +1,9 @@
-use std::convert::TryInto;
+use std::convert::TryFrom;
 
 use super::line::Line;
-use super::message::Message;
+use super::message::{Message, MessageRef};
 use super::word::Word;
-use crate::util::{expect_word, parsei64};
+use crate::util::{expect_word, Words};
 
 pub(super) enum InternalReply {
     Normal(Reply),
@@ -17,7 +17,8 @@ pub(super) enum InternalReply {
 /// `Option`, if the `Reply` is that particular variant `Some(val)` is returned. Otherwise, `None`
 /// is returned.
 /// This is useful for quickly extracting the wanted response value.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Reply {
     /// Represents a succesful processing of a sent `Command`.
     Ok,
@@ -38,6 +39,52 @@ pub enum Reply {
 }
 
 impl Reply {
+    /// Encodes this `Reply` into reply lines with the given `tag`, without trailing newline.
+    ///
+    /// Most replies are encoded into a single line, but `Reply::History` is encoded into a line
+    /// per message, separated by newlines.
+    /// Fails if the reply contains a `Message` of which the timestamp can't be encoded.
+    ///
+    /// ```
+    /// use tomsg_rs::{Reply, Word};
+    /// use std::convert::TryFrom;
+    ///
+    /// let tag = <&Word>::try_from("42").unwrap();
+    /// assert_eq!(Reply::Number(3).encode(tag).unwrap(), "42 number 3");
+    /// assert_eq!(Reply::History(vec![]).encode(tag).unwrap(), "42 history 0");
+    /// ```
+    pub fn encode(&self, tag: &Word) -> Result<String, &'static str> {
+        let res = match self {
+            Reply::Ok => format!("{} ok", tag),
+            Reply::Number(n) => format!("{} number {}", tag, n),
+            Reply::Error(e) => format!("{} error {}", tag, e),
+            Reply::Name(n) => format!("{} name {}", tag, n),
+            Reply::List(l) => {
+                let mut res = format!("{} list {}", tag, l.len());
+                for item in l {
+                    res.push(' ');
+                    res.push_str(item);
+                }
+                res
+            }
+            Reply::Pong => format!("{} pong", tag),
+            Reply::History(h) => {
+                let mut res = format!("{} history {}", tag, h.len());
+                for (i, message) in h.iter().enumerate() {
+                    res.push_str(&format!(
+                        "\n{} history_message {} {}",
+                        tag,
+                        i,
+                        message.encode()?
+                    ));
+                }
+                res
+            }
+            Reply::Message(m) => format!("{} message {}", tag, m.encode()?),
+        };
+        Ok(res)
+    }
+
     #[must_use]
     pub fn ok(self) -> Option<()> {
         match self {
@@ -97,38 +144,46 @@ impl Reply {
 }
 
 /// returns the tag and the InternalReply
-pub(super) fn parse(s: &str) -> (Box<Word>, InternalReply) {
-    let words: Vec<_> = s.split(' ').collect();
-
-    let make = |command: InternalReply| -> (Box<Word>, InternalReply) {
-        let tag = words[0].to_string().try_into().unwrap();
-        (tag, command)
-    };
-    let make_normal =
-        |command: Reply| -> (Box<Word>, InternalReply) { make(InternalReply::Normal(command)) };
-
-    let expect_line = |s: String| s.try_into().unwrap();
+pub(super) fn parse(s: &str) -> Result<(Box<Word>, InternalReply), String> {
+    let mut words = Words::new(s);
 
-    match words[1] {
-        "ok" => make_normal(Reply::Ok),
-        "number" => make_normal(Reply::Number(parsei64(words[2]))),
-        "error" => make_normal(Reply::Error(expect_line(words[2..].join(" ")))),
-        "name" => make_normal(Reply::Name(expect_word(words[2]))),
-        "list" => make_normal(Reply::List(words[3..].iter().map(expect_word).collect())),
-        "pong" => make_normal(Reply::Pong),
+    let tag = words.word("tag")?.to_owned();
+    let reply = match words.word("type")?.as_str() {
+        "ok" => InternalReply::Normal(Reply::Ok),
+        "number" => InternalReply::Normal(Reply::Number(words.number("number")?)),
+        "error" => {
+            let line = match words.rest("error") {
+                Ok(line) => line.to_owned(),
+                Err(_) => Box::<Line>::try_from(String::new()).unwrap(),
+            };
+            InternalReply::Normal(Reply::Error(line))
+        }
+        "name" => InternalReply::Normal(Reply::Name(words.word("name")?.to_owned())),
+        "list" => {
+            words.number::<i64>("count")?;
+            let items = words.remaining().map(expect_word).collect();
+            return Ok((tag, InternalReply::Normal(Reply::List(items))));
+        }
+        "pong" => InternalReply::Normal(Reply::Pong),
         "message" => {
-            let message = Message::try_parse(&words[2..]).unwrap();
-            make_normal(Reply::Message(message))
+            let message = MessageRef::parse(&mut words)?.into_owned();
+            InternalReply::Normal(Reply::Message(message))
         }
 
         // still needs to be handled
-        "history" => make(InternalReply::HistoryInit(parsei64(words[2]))),
+        "history" => match words.number::<i64>("count")? {
+            count if count < 0 => return Err(format!("got negative history count: {}", count)),
+            count => InternalReply::HistoryInit(count),
+        },
         "history_message" => {
-            let index = parsei64(words[2]);
-            let message = Message::try_parse(&words[3..]).unwrap();
-            make(InternalReply::HistoryMessage(index, message))
+            let index = words.number("index")?;
+            let message = MessageRef::parse(&mut words)?.into_owned();
+            InternalReply::HistoryMessage(index, message)
         }
 
-        w => panic!("unexpected response type: '{}'", w),
-    }
+        w => return Err(format!("unexpected response type: '{}'", w)),
+    };
+
+    words.end()?;
+    Ok((tag, reply))
 }
@@ -0,0 +1,265 @@
+//! Holds helpers to send text that doesn't fit in a single message, and to reassemble it.
+//!
+//! A `Line` can't contain newlines, and servers may limit the length of messages.
+//! `send_split` splits text into multiple `Command::Send` commands, marking every part as the
+//! continuation of the previous part using the given `Continuation` convention.
+//! `reassemble` reverses this on received messages.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use crate::command::Command;
+use crate::connection::{Connection, Error};
+use crate::id::Id;
+use crate::line::Line;
+use crate::message::Message;
+use crate::names::{RoomName, Username};
+use crate::reply::Reply;
+
+/// The way in which the parts of a split message are marked as continuations.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Continuation {
+    /// Every part except the first replies on the previous part.
+    ReplyChain,
+    /// Every part except the last ends with the given marker.
+    Marker(Box<Line>),
+}
+
+/// Options on how to split text into multiple messages.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SplitOptions {
+    /// The maximum length of a single message in bytes, including a continuation marker.
+    pub max_length: usize,
+    /// The way in which parts are marked as continuations.
+    pub continuation: Continuation,
+}
+
+impl Default for SplitOptions {
+    fn default() -> Self {
+        Self {
+            max_length: 1024,
+            continuation: Continuation::ReplyChain,
+        }
+    }
+}
+
+/// Returns the largest char boundary in `s` that is at most `index`.
+fn floor_char_boundary(s: &str, mut index: usize) -> usize {
+    while !s.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+/// Splits the given `text` into lines of at most `max_length` bytes.
+///
+/// The text is split at every newline, lines that are too long are wrapped at whitespace if
+/// possible.
+/// The whitespace at which a line is wrapped is kept at the end of the part, so `reassemble`
+/// can tell wrapped lines from separate lines.
+/// For the same reason, a line that ends with whitespace is followed by an empty part.
+/// Empty lines are kept as empty parts.
+///
+/// ```
+/// use tomsg_rs::split::split_text;
+///
+/// let parts = split_text("first line\n\nsecond line", 8);
+/// let parts: Vec<_> = parts.iter().map(|l| l.as_str()).collect();
+/// assert_eq!(parts, vec!["first ", "line", "", "second ", "line"]);
+/// ```
+///
+/// # Panics
+/// Panics if `max_length` is smaller than 4, since not every character would fit.
+#[must_use]
+pub fn split_text(text: &str, max_length: usize) -> Vec<Box<Line>> {
+    assert!(max_length >= 4, "max_length must be at least 4");
+
+    let mut parts = vec![];
+    for mut line in text.split('\n') {
+        while line.len() > max_length {
+            let end = floor_char_boundary(line, max_length);
+
+            // prefer wrapping at whitespace, which stays at the end of the part.
+            let (part, rest) = match line[..end].rfind(char::is_whitespace) {
+                Some(i) => {
+                    let whitespace = line[i..].chars().next().unwrap();
+                    line.split_at(i + whitespace.len_utf8())
+                }
+                None => line.split_at(end),
+            };
+            parts.push(part);
+            line = rest;
+        }
+        parts.push(line);
+
+        // otherwise the line would be joined with the next line, as if it was wrapped.
+        if line.ends_with(char::is_whitespace) {
+            parts.push("");
+        }
+    }
+
+    parts
+        .into_iter()
+        .map(|p| Box::<Line>::try_from(p.to_owned()).unwrap())
+        .collect()
+}
+
+/// Sends the given `text` to the room with the given `roomname`, split over as many messages as
+/// needed.
+///
+/// The first message replies on `reply_on`, the way the other messages are marked as
+/// continuations is determined by `options`.
+/// Returns the IDs of the sent messages, in order.
+///
+/// If sending a part fails, the error is returned and the remaining parts are not sent.
+///
+/// # Panics
+/// Panics if `options.max_length` leaves less than 4 bytes for text next to the continuation
+/// marker.
+pub async fn send_split(
+    conn: &Connection,
+    roomname: &RoomName,
+    reply_on: Option<Id>,
+    text: &str,
+    options: &SplitOptions,
+) -> Result<Vec<Id>, Error> {
+    let mut parts = match &options.continuation {
+        Continuation::ReplyChain => split_text(text, options.max_length),
+        Continuation::Marker(marker) => {
+            let mut parts = split_text(text, options.max_length.saturating_sub(marker.len()));
+            let last = parts.pop();
+            for part in &mut parts {
+                *part = Box::<Line>::try_from(format!("{}{}", part, marker)).unwrap();
+            }
+            parts.extend(last);
+            parts
+        }
+    };
+
+    let mut ids: Vec<Id> = Vec::with_capacity(parts.len());
+    for message in parts.drain(..) {
+        let reply_on = match (&options.continuation, ids.last()) {
+            (Continuation::ReplyChain, Some(previous)) => Some(*previous),
+            _ => reply_on,
+        };
+        let command = Command::Send {
+            roomname: roomname.into(),
+            reply_on,
+            message: message.into(),
+        };
+
+        let id = match conn.request(&command).await? {
+            Reply::Number(n) => {
+                Id::try_from(n).map_err(|_| Error::UnexpectedReply(Reply::Number(n)))?
+            }
+            r => return Err(Error::UnexpectedReply(r)),
+        };
+        ids.push(id);
+    }
+
+    Ok(ids)
+}
+
+/// A message that is reassembled from one or more parts.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Reassembled<'a> {
+    /// The messages this message consists of, in order.
+    pub parts: Vec<&'a Message>,
+    /// The text of the message.
+    ///
+    /// A part ending with whitespace is a wrapped line and is joined directly with the next
+    /// part, other parts are joined by newlines.
+    pub text: String,
+}
+
+/// Reassembles the given `messages`, which were split using the given `continuation`
+/// convention.
+///
+/// `messages` should be in chronological order, such as returned by `Command::History`.
+/// Parts are joined if they are sent by the same user in the same room, messages of other users
+/// in between are allowed.
+/// Note that with `Continuation::ReplyChain` a user replying on its own message can't be
+/// distinguished from a split message, so such replies are joined as well.
+///
+/// The reassembled messages are ordered by their first part.
+///
+/// The text of a message split by `split_text` is reassembled exactly, except that a word that
+/// is longer than a part is split by a newline.
+#[must_use]
+pub fn reassemble<'a>(
+    messages: &'a [Message],
+    continuation: &Continuation,
+) -> Vec<Reassembled<'a>> {
+    let mut groups: Vec<Vec<&'a Message>> = vec![];
+
+    // the index in `groups` of every group which is expected to be continued, keyed by the ID of
+    // its last message.
+    let mut open: HashMap<Id, usize> = HashMap::new();
+    // the IDs of the last messages of open groups, keyed by room and user, for markers.
+    let mut open_by_author: HashMap<(&RoomName, &Username), Id> = HashMap::new();
+
+    for message in messages {
+        let author = (&*message.roomname, &*message.username);
+        let previous = match continuation {
+            Continuation::ReplyChain => message.reply_on,
+            Continuation::Marker(_) => open_by_author.remove(&author),
+        };
+
+        // the group is only continued by its own author, a reply of someone else leaves it open.
+        let continued = previous.and_then(|id| {
+            let i = *open.get(&id)?;
+            let head = groups[i][0];
+            if head.roomname != message.roomname || head.username != message.username {
+                return None;
+            }
+            open.remove(&id);
+            Some(i)
+        });
+        let index = match continued {
+            Some(i) => {
+                groups[i].push(message);
+                i
+            }
+            None => {
+                groups.push(vec![message]);
+                groups.len() - 1
+            }
+        };
+
+        match continuation {
+            Continuation::ReplyChain => {
+                open.insert(message.id, index);
+            }
+            Continuation::Marker(marker) => {
+                if message.message.ends_with(marker.as_str()) {
+                    open.insert(message.id, index);
+                    open_by_author.insert(author, message.id);
+                }
+            }
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|parts| {
+            let count = parts.len();
+            let mut text = String::new();
+            let mut previous: Option<&str> = None;
+            for (i, m) in parts.iter().enumerate() {
+                let part = match continuation {
+                    Continuation::Marker(marker) if i + 1 < count => m
+                        .message
+                        .strip_suffix(marker.as_str())
+                        .unwrap_or(&m.message),
+                    _ => m.message.as_str(),
+                };
+                if previous.is_some_and(|p| !p.ends_with(char::is_whitespace)) {
+                    text.push('\n');
+                }
+                text.push_str(part);
+                previous = Some(part);
+            }
+            Reassembled { parts, text }
+        })
+        .collect()
+}
@@ -0,0 +1,156 @@
+//! Holds a tracker of the rooms a client participates in, and their members.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::command::Command;
+use crate::connection::{Connection, Error};
+use crate::names::{RoomName, Username};
+use crate::pushmessage::PushMessage;
+use crate::reply::Reply;
+
+/// The rooms the logged-in user participates in, and the members of those rooms.
+///
+/// A `ClientState` is created using `ClientState::bootstrap`, after which it is kept up to date
+/// by passing every received `PushMessage` to `ClientState::apply`.
+///
+/// ```
+/// use std::convert::TryFrom;
+/// use tomsg_rs::state::ClientState;
+/// use tomsg_rs::{PushMessage, RoomName, Username};
+///
+/// let me = <&Username>::try_from("me").unwrap();
+/// let room = <&RoomName>::try_from("room").unwrap();
+/// let other = <&Username>::try_from("other").unwrap();
+///
+/// let mut state = ClientState::new(me);
+/// state.apply(&PushMessage::Invite {
+///     roomname: room.to_owned(),
+///     inviter: other.to_owned(),
+/// });
+/// assert!(state.is_member(room, other));
+///
+/// state.apply(&PushMessage::Leave {
+///     roomname: room.to_owned(),
+///     username: me.to_owned(),
+/// });
+/// assert!(!state.contains_room(room));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientState {
+    username: Box<Username>,
+    rooms: BTreeMap<Box<RoomName>, BTreeSet<Box<Username>>>,
+}
+
+impl ClientState {
+    /// Creates a new `ClientState` for the user with the given `username`, without any rooms.
+    #[must_use]
+    pub fn new(username: &Username) -> Self {
+        Self {
+            username: username.to_owned(),
+            rooms: BTreeMap::new(),
+        }
+    }
+
+    /// Creates a new `ClientState` for the user with the given `username`, which should be
+    /// logged in on `conn`.
+    ///
+    /// The rooms are retrieved using `Command::ListRooms`, and the members of every room using
+    /// `Command::ListMembers`.
+    pub async fn bootstrap(conn: &Connection, username: &Username) -> Result<Self, Error> {
+        let mut state = Self::new(username);
+
+        let rooms = match conn.request(&Command::ListRooms).await? {
+            Reply::List(rooms) => rooms,
+            r => return Err(Error::UnexpectedReply(r)),
+        };
+        for roomname in rooms {
+            state
+                .refresh_room(conn, &RoomName::from_boxed_word(roomname))
+                .await?;
+        }
+
+        Ok(state)
+    }
+
+    /// Retrieves the members of the room with the given `roomname` using
+    /// `Command::ListMembers`, replacing the currently known members.
+    ///
+    /// A `PushMessage::Invite` only contains the inviter, so this can be used to retrieve the
+    /// other members of a room the user is invited in.
+    pub async fn refresh_room(
+        &mut self,
+        conn: &Connection,
+        roomname: &RoomName,
+    ) -> Result<(), Error> {
+        let command = Command::ListMembers {
+            roomname: roomname.into(),
+        };
+        let members = match conn.request(&command).await? {
+            Reply::List(members) => members,
+            r => return Err(Error::UnexpectedReply(r)),
+        };
+
+        let members = members.into_iter().map(Username::from_boxed_word).collect();
+        self.rooms.insert(roomname.to_owned(), members);
+        Ok(())
+    }
+
+    /// Updates this `ClientState` using the given `push`.
+    ///
+    /// Pushes which don't change the rooms or their members are ignored.
+    pub fn apply(&mut self, push: &PushMessage) {
+        match push {
+            PushMessage::Invite { roomname, inviter } => {
+                let members = self.rooms.entry(roomname.clone()).or_default();
+                members.insert(self.username.clone());
+                members.insert(inviter.clone());
+            }
+            PushMessage::Join { roomname, username } => {
+                if let Some(members) = self.rooms.get_mut(&**roomname) {
+                    members.insert(username.clone());
+                }
+            }
+            PushMessage::Leave { roomname, username } if *username == self.username => {
+                self.rooms.remove(&**roomname);
+            }
+            PushMessage::Leave { roomname, username } => {
+                if let Some(members) = self.rooms.get_mut(&**roomname) {
+                    members.remove(&**username);
+                }
+            }
+            PushMessage::Online { .. } | PushMessage::Message(_) => {}
+        }
+    }
+
+    /// The username of the user this `ClientState` belongs to.
+    #[must_use]
+    pub fn username(&self) -> &Username {
+        &self.username
+    }
+
+    /// Returns an iterator over the names of the rooms the user participates in.
+    pub fn rooms(&self) -> impl Iterator<Item = &RoomName> {
+        self.rooms.keys().map(|r| &**r)
+    }
+
+    /// Returns whether the user participates in the room with the given `roomname`.
+    #[must_use]
+    pub fn contains_room(&self, roomname: &RoomName) -> bool {
+        self.rooms.contains_key(roomname)
+    }
+
+    /// Returns an iterator over the members of the room with the given `roomname`, or `None` if
+    /// the user doesn't participate in the room.
+    pub fn members(&self, roomname: &RoomName) -> Option<impl Iterator<Item = &Username>> {
+        self.rooms.get(roomname).map(|m| m.iter().map(|u| &**u))
+    }
+
+    /// Returns whether the user with the given `username` is a member of the room with the given
+    /// `roomname`.
+    #[must_use]
+    pub fn is_member(&self, roomname: &RoomName, username: &Username) -> bool {
+        self.rooms
+            .get(roomname)
+            .is_some_and(|members| members.contains(username))
+    }
+}
@@ -0,0 +1,465 @@
+//! Holds a persistent message store, backed by SQLite.
+//!
+//! This module is only available with the `sqlite` feature enabled.
+//! Full-text search over the stored messages is available with the `search` feature enabled.
+
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures_util::StreamExt;
+use rusqlite::types::Type;
+use rusqlite::{params, OptionalExtension, Row};
+
+use crate::connection::{self, Connection};
+use crate::id::Id;
+use crate::line::Line;
+use crate::message::Message;
+use crate::names::{RoomName, Username};
+use crate::pushmessage::PushMessage;
+use crate::timestamp;
+
+#[cfg(feature = "search")]
+mod search;
+#[cfg(feature = "search")]
+pub use search::{SearchQuery, SearchResult};
+
+/// An error that occured while using a `MessageStore`.
+#[derive(Debug)]
+pub enum Error {
+    /// The SQLite database returned an error.
+    Sqlite(rusqlite::Error),
+    /// Retrieving messages from the server failed.
+    Connection(connection::Error),
+    /// The message can't be stored, for example because its timestamp is before the UNIX epoch.
+    InvalidMessage(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Sqlite(e) => write!(f, "sqlite error: {}", e),
+            Error::Connection(e) => write!(f, "{}", e),
+            Error::InvalidMessage(e) => write!(f, "invalid message: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Sqlite(e) => Some(e),
+            Error::Connection(e) => Some(e),
+            Error::InvalidMessage(_) => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Error::Sqlite(e)
+    }
+}
+
+impl From<connection::Error> for Error {
+    fn from(e: connection::Error) -> Self {
+        Error::Connection(e)
+    }
+}
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS messages (
+        id INTEGER PRIMARY KEY,
+        reply_on INTEGER,
+        roomname TEXT NOT NULL,
+        username TEXT NOT NULL,
+        timestamp INTEGER NOT NULL,
+        message TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS messages_room ON messages (roomname, id);
+    CREATE INDEX IF NOT EXISTS messages_room_time ON messages (roomname, timestamp);
+    CREATE TABLE IF NOT EXISTS synced (
+        roomname TEXT PRIMARY KEY,
+        id INTEGER NOT NULL
+    );
+";
+
+const COLUMNS: &str = "id, reply_on, roomname, username, timestamp, message";
+
+/// Converts a column value into one of the types of this crate, turning a failed conversion
+/// into a `rusqlite::Error`.
+fn column<T, U>(
+    row: &Row<'_>,
+    index: usize,
+    convert: impl FnOnce(T) -> Result<U, &'static str>,
+) -> rusqlite::Result<U>
+where
+    T: rusqlite::types::FromSql,
+{
+    let value = row.get(index)?;
+    convert(value)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, e.into()))
+}
+
+fn row_to_message(row: &Row<'_>) -> rusqlite::Result<Message> {
+    Ok(Message {
+        id: column::<i64, _>(row, 0, Id::try_from)?,
+        reply_on: column(row, 1, |id: Option<i64>| id.map(Id::try_from).transpose())?,
+        roomname: column::<String, _>(row, 2, Box::<RoomName>::try_from)?,
+        username: column::<String, _>(row, 3, Box::<Username>::try_from)?,
+        timestamp: column(row, 4, |micros: i64| {
+            u64::try_from(micros)
+                .ok()
+                .and_then(timestamp::from_micros)
+                .ok_or("invalid timestamp")
+        })?,
+        message: column::<String, _>(row, 5, Box::<Line>::try_from)?,
+    })
+}
+
+fn encode_timestamp(timestamp: SystemTime) -> Result<i64, Error> {
+    let micros = timestamp::to_micros(timestamp).map_err(Error::InvalidMessage)?;
+    i64::try_from(micros).map_err(|_| Error::InvalidMessage("timestamp is too far in the future"))
+}
+
+/// Converts `timestamp` into stored microseconds, clamping it to the range of valid timestamps.
+fn clamp_timestamp(timestamp: SystemTime) -> i64 {
+    if timestamp < UNIX_EPOCH {
+        return 0;
+    }
+    encode_timestamp(timestamp).unwrap_or(i64::MAX)
+}
+
+/// Converts `bounds` into an inclusive range of raw IDs.
+fn id_range(bounds: &impl RangeBounds<Id>) -> (i64, i64) {
+    let start = match bounds.start_bound() {
+        Bound::Included(id) => i64::from(id),
+        Bound::Excluded(id) => i64::from(id).saturating_add(1),
+        Bound::Unbounded => 0,
+    };
+    let end = match bounds.end_bound() {
+        Bound::Included(id) => i64::from(id),
+        Bound::Excluded(id) => i64::from(id) - 1,
+        Bound::Unbounded => i64::MAX,
+    };
+    (start, end)
+}
+
+/// A persistent store of `Message` instances, backed by a SQLite database.
+///
+/// Messages are added using `MessageStore::insert` and `MessageStore::apply` for live pushes,
+/// and retrieved from the server using `MessageStore::sync` and `MessageStore::backfill`.
+/// The stored messages can be queried without a connection to the server.
+///
+/// ```
+/// use std::convert::TryFrom;
+/// use tomsg_rs::store::MessageStore;
+/// use tomsg_rs::{Id, PushMessage, RoomName};
+///
+/// let id = |id| Id::try_from(id).unwrap();
+/// let room = <&RoomName>::try_from("room").unwrap();
+/// let message = |i| {
+///     let line = format!("_push message room user {} {} -1 hi", i, i);
+///     match PushMessage::parse(&line).unwrap() {
+///         Some(PushMessage::Message(message)) => message,
+///         _ => unreachable!(),
+///     }
+/// };
+///
+/// let store = MessageStore::open_in_memory().unwrap();
+/// assert_eq!(store.insert_all(&[message(1), message(2), message(3)]).unwrap(), 3);
+/// assert!(!store.insert(&message(2)).unwrap());
+///
+/// assert_eq!(store.range(room, id(2).., 10).unwrap(), vec![message(2), message(3)]);
+/// assert_eq!(store.range(room, .., 1).unwrap(), vec![message(3)]);
+/// assert_eq!(store.latest(room).unwrap(), Some(id(3)));
+/// ```
+#[derive(Debug)]
+pub struct MessageStore {
+    db: Mutex<rusqlite::Connection>,
+}
+
+impl MessageStore {
+    /// Opens the `MessageStore` in the SQLite database at the given `path`, creating it if it
+    /// doesn't exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::from_connection(rusqlite::Connection::open(path)?)
+    }
+
+    /// Opens a `MessageStore` which is kept in memory, and thus not persisted.
+    pub fn open_in_memory() -> Result<Self, Error> {
+        Self::from_connection(rusqlite::Connection::open_in_memory()?)
+    }
+
+    fn from_connection(db: rusqlite::Connection) -> Result<Self, Error> {
+        db.execute_batch(SCHEMA)?;
+        #[cfg(feature = "search")]
+        search::create_index(&db)?;
+        Ok(Self { db: Mutex::new(db) })
+    }
+
+    /// Adds the given `message`, returns whether it wasn't stored yet.
+    pub fn insert(&self, message: &Message) -> Result<bool, Error> {
+        self.insert_all(std::slice::from_ref(message))
+            .map(|inserted| inserted == 1)
+    }
+
+    /// Adds every message in `messages` in a single transaction, returns the amount of messages
+    /// that weren't stored yet.
+    pub fn insert_all(&self, messages: &[Message]) -> Result<usize, Error> {
+        self.insert_synced(messages, None)
+    }
+
+    /// Like `MessageStore::insert_all`, but also records in the same transaction that the
+    /// messages of a room are retrieved without gaps up to the given ID.
+    fn insert_synced(
+        &self,
+        messages: &[Message],
+        synced: Option<(&RoomName, Id)>,
+    ) -> Result<usize, Error> {
+        let mut db = self.db.lock().unwrap();
+        let transaction = db.transaction()?;
+        let mut inserted = 0;
+        {
+            let mut statement = transaction.prepare_cached(&format!(
+                "INSERT OR IGNORE INTO messages ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                COLUMNS
+            ))?;
+            for message in messages {
+                inserted += statement.execute(params![
+                    i64::from(message.id),
+                    message.reply_on.map(i64::from),
+                    message.roomname.as_str(),
+                    message.username.as_str(),
+                    encode_timestamp(message.timestamp)?,
+                    message.message.as_str(),
+                ])?;
+            }
+        }
+        if let Some((roomname, id)) = synced {
+            transaction.execute(
+                "INSERT INTO synced (roomname, id) VALUES (?1, ?2) \
+                 ON CONFLICT (roomname) DO UPDATE SET id = MAX(id, excluded.id)",
+                params![roomname.as_str(), i64::from(id)],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(inserted)
+    }
+
+    /// Adds the message in the given `push`, if it is a `PushMessage::Message`.
+    pub fn apply(&self, push: &PushMessage) -> Result<(), Error> {
+        if let PushMessage::Message(message) = push {
+            self.insert(message)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the stored message with the given `id`.
+    pub fn get(&self, id: Id) -> Result<Option<Message>, Error> {
+        let db = self.db.lock().unwrap();
+        let message = db
+            .query_row(
+                &format!("SELECT {} FROM messages WHERE id = ?1", COLUMNS),
+                params![i64::from(id)],
+                row_to_message,
+            )
+            .optional()?;
+        Ok(message)
+    }
+
+    fn query(&self, sql: &str, params: impl rusqlite::Params) -> Result<Vec<Message>, Error> {
+        let db = self.db.lock().unwrap();
+        let mut statement = db.prepare_cached(sql)?;
+        let messages = statement
+            .query_map(params, row_to_message)?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(messages)
+    }
+
+    /// Returns the newest `limit` stored messages in the room with the given `roomname` of which
+    /// the ID is in `ids`, oldest first.
+    pub fn range(
+        &self,
+        roomname: &RoomName,
+        ids: impl RangeBounds<Id>,
+        limit: usize,
+    ) -> Result<Vec<Message>, Error> {
+        let (start, end) = id_range(&ids);
+        let mut messages = self.query(
+            &format!(
+                "SELECT {} FROM messages WHERE roomname = ?1 AND id BETWEEN ?2 AND ?3 \
+                 ORDER BY id DESC LIMIT ?4",
+                COLUMNS
+            ),
+            params![
+                roomname.as_str(),
+                start,
+                end,
+                i64::try_from(limit).unwrap_or(i64::MAX)
+            ],
+        )?;
+        messages.reverse();
+        Ok(messages)
+    }
+
+    /// Returns the newest `limit` stored messages in the room with the given `roomname` which
+    /// were sent at or after `from` and before `to`, oldest first.
+    pub fn time_range(
+        &self,
+        roomname: &RoomName,
+        from: SystemTime,
+        to: SystemTime,
+        limit: usize,
+    ) -> Result<Vec<Message>, Error> {
+        let from = clamp_timestamp(from);
+        let to = clamp_timestamp(to);
+        let mut messages = self.query(
+            &format!(
+                "SELECT {} FROM messages WHERE roomname = ?1 AND timestamp >= ?2 \
+                 AND timestamp < ?3 ORDER BY id DESC LIMIT ?4",
+                COLUMNS
+            ),
+            params![
+                roomname.as_str(),
+                from,
+                to,
+                i64::try_from(limit).unwrap_or(i64::MAX)
+            ],
+        )?;
+        messages.reverse();
+        Ok(messages)
+    }
+
+    fn id_query(&self, sql: &str, roomname: &RoomName) -> Result<Option<Id>, Error> {
+        let db = self.db.lock().unwrap();
+        let id: Option<i64> = db.query_row(sql, params![roomname.as_str()], |row| row.get(0))?;
+        Ok(id.and_then(|id| Id::try_from(id).ok()))
+    }
+
+    /// Returns the ID of the newest stored message in the room with the given `roomname`.
+    pub fn latest(&self, roomname: &RoomName) -> Result<Option<Id>, Error> {
+        self.id_query("SELECT MAX(id) FROM messages WHERE roomname = ?1", roomname)
+    }
+
+    /// Returns the ID of the oldest stored message in the room with the given `roomname`.
+    pub fn oldest(&self, roomname: &RoomName) -> Result<Option<Id>, Error> {
+        self.id_query("SELECT MIN(id) FROM messages WHERE roomname = ?1", roomname)
+    }
+
+    /// Returns the ID up to which the messages in the room with the given `roomname` are
+    /// retrieved without gaps by `MessageStore::sync` and `MessageStore::backfill`.
+    ///
+    /// Unlike `MessageStore::latest`, this isn't affected by messages added using
+    /// `MessageStore::insert` or `MessageStore::apply`.
+    pub fn synced(&self, roomname: &RoomName) -> Result<Option<Id>, Error> {
+        self.id_query("SELECT MAX(id) FROM synced WHERE roomname = ?1", roomname)
+    }
+
+    /// Returns the names of every room of which messages are stored.
+    pub fn rooms(&self) -> Result<Vec<Box<RoomName>>, Error> {
+        let db = self.db.lock().unwrap();
+        let mut statement = db.prepare_cached("SELECT DISTINCT roomname FROM messages")?;
+        let rooms = statement
+            .query_map([], |row| {
+                column::<String, _>(row, 0, Box::<RoomName>::try_from)
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(rooms)
+    }
+
+    /// Retrieves the messages in the room with the given `roomname` that are newer than
+    /// `MessageStore::synced`, in pages of `page_size` messages.
+    ///
+    /// Messages added using `MessageStore::apply`, such as live pushes, don't count as synced,
+    /// so a push received before syncing doesn't hide the messages before it.
+    /// If the room was never synced, only the newest page is retrieved, use
+    /// `MessageStore::backfill` to retrieve older messages.
+    /// The messages are stored at once when all of them are retrieved, so an interrupted sync
+    /// doesn't leave a gap between the stored messages.
+    /// Returns the amount of stored messages.
+    ///
+    /// # Panics
+    /// Panics if `page_size` is not positive.
+    pub async fn sync(
+        &self,
+        conn: &Connection,
+        roomname: &RoomName,
+        page_size: i64,
+    ) -> Result<usize, Error> {
+        let synced = self.synced(roomname)?;
+
+        let mut pages = Box::pin(conn.history_pages(roomname, page_size));
+        let mut messages = vec![];
+        while let Some(message) = pages.next().await {
+            let message = message?;
+            if synced.is_some_and(|synced| message.id <= synced) {
+                break;
+            }
+            messages.push(message);
+            if synced.is_none() && messages.len() as i64 == page_size {
+                break;
+            }
+        }
+
+        // the messages are yielded newest first.
+        let newest = messages.first().map(|m| (roomname, m.id));
+        self.insert_synced(&messages, newest)
+    }
+
+    /// Retrieves at most `count` messages in the room with the given `roomname` that are older
+    /// than the oldest stored message, in pages of `page_size` messages.
+    ///
+    /// Every page is stored as soon as it is retrieved.
+    /// Returns the amount of stored messages, which is less than `count` when the first message
+    /// of the room is reached.
+    ///
+    /// # Panics
+    /// Panics if `page_size` is not positive.
+    pub async fn backfill(
+        &self,
+        conn: &Connection,
+        roomname: &RoomName,
+        page_size: i64,
+        count: usize,
+    ) -> Result<usize, Error> {
+        let oldest = self.oldest(roomname)?;
+        let mut pages = match oldest {
+            None => Box::pin(conn.history_pages(roomname, page_size)).left_stream(),
+            Some(oldest) => {
+                Box::pin(conn.history_pages_before(roomname, oldest, page_size)).right_stream()
+            }
+        };
+
+        // when starting at the newest message, `sync` can continue after the first page.
+        let mut from_newest = oldest.is_none();
+        let mut store_page = |page: &[Message]| {
+            let synced = match page.first() {
+                Some(newest) if from_newest => Some((roomname, newest.id)),
+                _ => None,
+            };
+            from_newest = false;
+            self.insert_synced(page, synced)
+        };
+
+        let mut stored = 0;
+        let mut page = vec![];
+        while stored + page.len() < count {
+            match pages.next().await {
+                None => break,
+                Some(message) => page.push(message?),
+            }
+            if page.len() as i64 == page_size {
+                stored += store_page(&page)?;
+                page.clear();
+            }
+        }
+        stored += store_page(&page)?;
+
+        Ok(stored)
+    }
+}
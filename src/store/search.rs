@@ -0,0 +1,159 @@
+//! Holds the full-text search index of the `MessageStore`, backed by SQLite FTS5.
+//!
+//! This module is only available with the `search` feature enabled.
+
+use std::convert::TryFrom;
+use std::time::SystemTime;
+
+use rusqlite::params;
+
+use super::{clamp_timestamp, column, Error, MessageStore};
+use crate::id::Id;
+use crate::names::{RoomName, Username};
+
+const SCHEMA: &str = "
+    CREATE VIRTUAL TABLE messages_fts USING fts5(
+        message,
+        content = 'messages',
+        content_rowid = 'id'
+    );
+    CREATE TRIGGER messages_fts_insert AFTER INSERT ON messages BEGIN
+        INSERT INTO messages_fts (rowid, message) VALUES (new.id, new.message);
+    END;
+    INSERT INTO messages_fts (messages_fts) VALUES ('rebuild');
+";
+
+/// Creates the search index if it doesn't exist yet, indexing the messages that are already
+/// stored.
+pub(super) fn create_index(db: &rusqlite::Connection) -> rusqlite::Result<()> {
+    let exists: bool = db.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = 'messages_fts')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        db.execute_batch(SCHEMA)?;
+    }
+    Ok(())
+}
+
+/// Turns every word in `text` into a quoted FTS5 string, so that the words are matched
+/// literally.
+fn fts_query(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A full-text search query, executed using `MessageStore::search`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchQuery {
+    /// The text to search for, every word of which should occur in a matching message.
+    pub text: String,
+    /// If set, only messages in the room with this name match.
+    pub roomname: Option<Box<RoomName>>,
+    /// If set, only messages sent by the user with this username match.
+    pub username: Option<Box<Username>>,
+    /// If set, only messages sent at or after this time match.
+    pub from: Option<SystemTime>,
+    /// If set, only messages sent before this time match.
+    pub to: Option<SystemTime>,
+    /// The maximum amount of results.
+    pub limit: usize,
+    /// The text inserted before every matching word in the snippets.
+    pub highlight_start: String,
+    /// The text inserted after every matching word in the snippets.
+    pub highlight_end: String,
+}
+
+impl SearchQuery {
+    /// Creates a new `SearchQuery` for the given `text`, matching messages in any room sent at
+    /// any time, returning at most 50 results highlighted with `**`.
+    #[must_use]
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            roomname: None,
+            username: None,
+            from: None,
+            to: None,
+            limit: 50,
+            highlight_start: String::from("**"),
+            highlight_end: String::from("**"),
+        }
+    }
+}
+
+/// A message matching a `SearchQuery`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SearchResult {
+    /// The ID of the matching message, which can be retrieved using `MessageStore::get`.
+    pub id: Id,
+    /// The part of the message around the matching words, with every matching word surrounded
+    /// by the highlight markers of the `SearchQuery`.
+    pub snippet: String,
+}
+
+impl MessageStore {
+    /// Returns the stored messages matching the given `query`, most relevant first.
+    ///
+    /// Returns no results if the text of the `query` contains no words.
+    /// This method is only available with the `search` feature enabled.
+    ///
+    /// ```
+    /// use std::convert::TryFrom;
+    /// use tomsg_rs::store::{MessageStore, SearchQuery};
+    /// use tomsg_rs::{Id, PushMessage};
+    ///
+    /// let push = |line: &str| PushMessage::parse(line).unwrap().unwrap();
+    ///
+    /// let store = MessageStore::open_in_memory().unwrap();
+    /// store.apply(&push("_push message room user 0 1 -1 the quick brown fox")).unwrap();
+    /// store.apply(&push("_push message room user 0 2 -1 a lazy dog")).unwrap();
+    ///
+    /// let results = store.search(&SearchQuery::new("fox")).unwrap();
+    /// assert_eq!(results.len(), 1);
+    /// assert_eq!(results[0].id, Id::try_from(1).unwrap());
+    /// assert_eq!(results[0].snippet, "the quick brown **fox**");
+    /// ```
+    pub fn search(&self, query: &SearchQuery) -> Result<Vec<SearchResult>, Error> {
+        let text = fts_query(&query.text);
+        if text.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let db = self.db.lock().unwrap();
+        let mut statement = db.prepare_cached(
+            "SELECT messages.id, snippet(messages_fts, 0, ?2, ?3, '…', 16) \
+             FROM messages_fts JOIN messages ON messages.id = messages_fts.rowid \
+             WHERE messages_fts MATCH ?1 \
+             AND (?4 IS NULL OR messages.roomname = ?4) \
+             AND (?5 IS NULL OR messages.username = ?5) \
+             AND (?6 IS NULL OR messages.timestamp >= ?6) \
+             AND (?7 IS NULL OR messages.timestamp < ?7) \
+             ORDER BY rank LIMIT ?8",
+        )?;
+        let results = statement
+            .query_map(
+                params![
+                    text,
+                    query.highlight_start,
+                    query.highlight_end,
+                    query.roomname.as_ref().map(|r| r.as_str()),
+                    query.username.as_ref().map(|u| u.as_str()),
+                    query.from.map(clamp_timestamp),
+                    query.to.map(clamp_timestamp),
+                    i64::try_from(query.limit).unwrap_or(i64::MAX),
+                ],
+                |row| {
+                    Ok(SearchResult {
+                        id: column::<i64, _>(row, 0, Id::try_from)?,
+                        snippet: row.get(1)?,
+                    })
+                },
+            )?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(results)
+    }
+}
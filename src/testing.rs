@@ -0,0 +1,210 @@
+//! Holds a scripted tomsg server, for testing clients built on this crate.
+//!
+//! This module is only available with the `testing` feature enabled.
+
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::command::Command;
+use crate::connection::{Connection, Type};
+use crate::line::Line;
+use crate::pushmessage::PushMessage;
+use crate::reply::Reply;
+use crate::word::Word;
+
+type Handler = Box<dyn FnMut(&Command<'_>) -> Reply + Send>;
+
+struct Shared {
+    handler: Mutex<Handler>,
+    queue: Mutex<VecDeque<Reply>>,
+    received: Mutex<Vec<String>>,
+    clients: Mutex<Vec<mpsc::UnboundedSender<String>>>,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Shared {
+    fn reply(&self, line: &str) -> Reply {
+        let command = match Command::parse(line) {
+            Ok(c) => c,
+            Err(e) => return Reply::Error(error_line(&e)),
+        };
+
+        if let Command::Version(_) = command {
+            return Reply::Ok;
+        }
+        if let Some(reply) = self.queue.lock().unwrap().pop_front() {
+            return reply;
+        }
+        (self.handler.lock().unwrap())(&command)
+    }
+
+    async fn serve(self: Arc<Self>, stream: TcpStream) {
+        let (reader, mut writer) = stream.into_split();
+
+        let (send, mut receive) = mpsc::unbounded_channel::<String>();
+        self.clients.lock().unwrap().push(send.clone());
+
+        let write_task = tokio::spawn(async move {
+            while let Some(line) = receive.recv().await {
+                if writer.write_all(line.as_bytes()).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut lines = BufReader::new(reader).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let (tag, line) = match line.find(' ') {
+                None => continue,
+                Some(i) => (&line[..i], &line[i + 1..]),
+            };
+            let tag = match <&Word>::try_from(tag) {
+                Ok(tag) => tag,
+                Err(_) => continue,
+            };
+
+            self.received.lock().unwrap().push(line.to_owned());
+            let reply = self.reply(line);
+            let reply = reply
+                .encode(tag)
+                .or_else(|e| Reply::Error(error_line(e)).encode(tag))
+                .unwrap();
+            if send.send(format!("{}\n", reply)).is_err() {
+                break;
+            }
+        }
+
+        write_task.abort();
+    }
+}
+
+fn error_line(s: &str) -> Box<Line> {
+    Box::<Line>::try_from(s.replace('\n', " ")).unwrap()
+}
+
+/// An in-process tomsg server which answers commands with programmable replies.
+///
+/// The server listens on a local port, so `Connection::connect` can be used to connect to it.
+/// Every `Command` sent to the server is answered as follows:
+/// - `Command::Version` is always answered with `Reply::Ok`;
+/// - otherwise, if replies have been queued using `MockServer::enqueue`, the first one is used;
+/// - otherwise, the handler set with `MockServer::set_handler` is called.
+///
+/// A reply that can't be encoded is sent as a `Reply::Error` instead.
+///
+/// The default handler answers `Command::Ping` with `Reply::Pong` and every other command with
+/// a `Reply::Error`.
+///
+/// ```
+/// use tomsg_rs::testing::MockServer;
+/// use tomsg_rs::{Command, Reply};
+///
+/// # tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(async {
+/// let server = MockServer::bind().await.unwrap();
+/// server.enqueue(Reply::Number(42));
+///
+/// let (conn, _pushes) = server.connect().await.unwrap();
+/// let reply = conn.send(&Command::CreateRoom).await.unwrap().unwrap();
+/// assert_eq!(reply, Reply::Number(42));
+///
+/// assert_eq!(server.received(), vec!["version 4", "create_room"]);
+/// # });
+/// ```
+pub struct MockServer {
+    address: SocketAddr,
+    shared: Arc<Shared>,
+    accept_task: JoinHandle<()>,
+}
+
+impl MockServer {
+    /// Creates a new `MockServer` listening on a free port on the loopback interface.
+    pub async fn bind() -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let address = listener.local_addr()?;
+
+        let shared = Arc::new(Shared {
+            handler: Mutex::new(Box::new(|command| match command {
+                Command::Ping => Reply::Pong,
+                _ => Reply::Error(error_line("unexpected command")),
+            })),
+            queue: Mutex::new(VecDeque::new()),
+            received: Mutex::new(Vec::new()),
+            clients: Mutex::new(Vec::new()),
+            tasks: Mutex::new(Vec::new()),
+        });
+
+        let accept_shared = shared.clone();
+        let accept_task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let task = tokio::spawn(accept_shared.clone().serve(stream));
+                accept_shared.tasks.lock().unwrap().push(task);
+            }
+        });
+
+        Ok(Self {
+            address,
+            shared,
+            accept_task,
+        })
+    }
+
+    /// The address this `MockServer` is listening on.
+    #[must_use]
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Creates a new `Connection` to this `MockServer`.
+    pub async fn connect(&self) -> io::Result<(Connection, mpsc::Receiver<PushMessage>)> {
+        Connection::connect(Type::Plain, self.address).await
+    }
+
+    /// Sets the function that is called to answer commands, replacing the previous one.
+    pub fn set_handler(&self, handler: impl FnMut(&Command<'_>) -> Reply + Send + 'static) {
+        *self.shared.handler.lock().unwrap() = Box::new(handler);
+    }
+
+    /// Queues the given `reply`, which will be used to answer the next command.
+    pub fn enqueue(&self, reply: Reply) {
+        self.shared.queue.lock().unwrap().push_back(reply);
+    }
+
+    /// Sends the given `push` message to every client connected to this `MockServer`.
+    ///
+    /// # Panics
+    /// Panics if `push` can't be encoded.
+    pub fn push(&self, push: &PushMessage) {
+        let line = format!(
+            "{}\n",
+            push.encode().expect("push message can't be encoded")
+        );
+        self.shared
+            .clients
+            .lock()
+            .unwrap()
+            .retain(|client| client.send(line.clone()).is_ok());
+    }
+
+    /// Returns every command line received by this `MockServer` so far, in order, without tags.
+    #[must_use]
+    pub fn received(&self) -> Vec<String> {
+        self.shared.received.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.accept_task.abort();
+        for task in self.shared.tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
+    }
+}
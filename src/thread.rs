@@ -0,0 +1,185 @@
+//! Holds a builder of reply trees, following `Message::reply_on` links.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::command::Command;
+use crate::connection::{Connection, Error};
+use crate::id::Id;
+use crate::message::Message;
+use crate::pushmessage::PushMessage;
+use crate::reply::Reply;
+
+/// A forest of reply trees, in which every message is a child of the message it replies on.
+///
+/// Messages are added using `Threads::insert`, `Threads::extend` and `Threads::apply`, in any
+/// order.
+/// A message of which the parent is not known is a root, until the parent is added; use
+/// `Threads::fetch_ancestors` to retrieve missing parents from the server.
+///
+/// ```
+/// use std::convert::TryFrom;
+/// use tomsg_rs::thread::Threads;
+/// use tomsg_rs::{Id, PushMessage};
+///
+/// let id = |id| Id::try_from(id).unwrap();
+/// let push = |line: &str| PushMessage::parse(line).unwrap().unwrap();
+///
+/// let mut threads = Threads::new();
+/// threads.apply(&push("_push message room user 0 3 2 hi"));
+/// threads.apply(&push("_push message room user 0 1 -1 hi"));
+/// assert_eq!(threads.roots().count(), 2);
+///
+/// threads.apply(&push("_push message room user 0 2 1 hi"));
+/// let roots: Vec<_> = threads.roots().map(|m| m.id).collect();
+/// assert_eq!(roots, vec![id(1)]);
+/// assert_eq!(threads.depth(id(3)), Some(2));
+/// assert_eq!(threads.root(id(3)).unwrap().id, id(1));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Threads {
+    messages: HashMap<Id, Message>,
+    /// The IDs of the replies on every message, which may not have been added yet.
+    children: HashMap<Id, BTreeSet<Id>>,
+    /// The IDs of messages that couldn't be retrieved from the server.
+    unavailable: HashSet<Id>,
+}
+
+impl Threads {
+    /// Creates a new `Threads` without any messages.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the given `message`, linking it to its parent and replies.
+    pub fn insert(&mut self, message: Message) {
+        if let Some(parent) = message.reply_on {
+            self.children.entry(parent).or_default().insert(message.id);
+        }
+        self.unavailable.remove(&message.id);
+        self.messages.insert(message.id, message);
+    }
+
+    /// Adds every message in `messages`, such as the messages of a `Reply::History`.
+    pub fn extend(&mut self, messages: impl IntoIterator<Item = Message>) {
+        for message in messages {
+            self.insert(message);
+        }
+    }
+
+    /// Adds the message in the given `push`, if it is a `PushMessage::Message`.
+    pub fn apply(&mut self, push: &PushMessage) {
+        if let PushMessage::Message(message) = push {
+            self.insert(message.clone());
+        }
+    }
+
+    /// Returns an iterator over the IDs of the messages that are replied on, but which are not
+    /// added yet.
+    ///
+    /// Messages that couldn't be retrieved by `Threads::fetch_ancestors` are excluded.
+    pub fn missing(&self) -> impl Iterator<Item = Id> + '_ {
+        self.children
+            .keys()
+            .copied()
+            .filter(move |id| !self.messages.contains_key(id) && !self.unavailable.contains(id))
+    }
+
+    /// Retrieves every missing ancestor of the added messages using `Command::GetMessage`.
+    ///
+    /// Messages which the server refuses to return, for example because they are in a room the
+    /// user left, are skipped and remain missing.
+    pub async fn fetch_ancestors(&mut self, conn: &Connection) -> Result<(), Error> {
+        loop {
+            let missing: Vec<Id> = self.missing().collect();
+            if missing.is_empty() {
+                return Ok(());
+            }
+
+            for id in missing {
+                match conn.request(&Command::GetMessage(id)).await {
+                    Ok(Reply::Message(m)) => self.insert(m),
+                    Ok(r) => return Err(Error::UnexpectedReply(r)),
+                    Err(Error::Server(_)) => {
+                        self.unavailable.insert(id);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+    }
+
+    /// Returns the message with the given `id`.
+    #[must_use]
+    pub fn get(&self, id: Id) -> Option<&Message> {
+        self.messages.get(&id)
+    }
+
+    /// Returns the message the message with the given `id` replies on, if it is added.
+    #[must_use]
+    pub fn parent(&self, id: Id) -> Option<&Message> {
+        self.messages.get(&self.get(id)?.reply_on?)
+    }
+
+    /// Returns an iterator over the added replies on the message with the given `id`, oldest
+    /// first.
+    pub fn children(&self, id: Id) -> impl Iterator<Item = &Message> {
+        self.children
+            .get(&id)
+            .into_iter()
+            .flatten()
+            .filter_map(move |id| self.messages.get(id))
+    }
+
+    /// Returns an iterator over the roots of the reply trees, which are the messages of which
+    /// the parent is not added, in no particular order.
+    pub fn roots(&self) -> impl Iterator<Item = &Message> {
+        self.messages.values().filter(move |message| {
+            message
+                .reply_on
+                .is_none_or(|parent| !self.messages.contains_key(&parent))
+        })
+    }
+
+    /// Returns the root of the reply tree the message with the given `id` is in.
+    #[must_use]
+    pub fn root(&self, id: Id) -> Option<&Message> {
+        let mut message = self.get(id)?;
+        // the amount of messages bounds the depth, even if the server returned a cycle.
+        for _ in 0..self.messages.len() {
+            match self.parent(message.id) {
+                Some(parent) => message = parent,
+                None => break,
+            }
+        }
+        Some(message)
+    }
+
+    /// Returns the depth of the message with the given `id` in its reply tree, where roots have
+    /// a depth of zero.
+    #[must_use]
+    pub fn depth(&self, id: Id) -> Option<usize> {
+        let mut message = self.get(id)?;
+        let mut depth = 0;
+        while let Some(parent) = self.parent(message.id) {
+            if depth == self.messages.len() {
+                break;
+            }
+            message = parent;
+            depth += 1;
+        }
+        Some(depth)
+    }
+
+    /// The amount of added messages.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// Returns whether no messages are added.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+}
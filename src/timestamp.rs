@@ -0,0 +1,124 @@
+//! Holds helpers for tomsg timestamps, which are sent as the amount of microseconds since the
+//! UNIX epoch.
+//!
+//! With the `chrono` or `time` feature enabled, the `chrono` and `time` submodules convert
+//! between `SystemTime` and the date types of those crates.
+
+use std::convert::TryFrom;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Converts the given `timestamp` into the amount of microseconds since the UNIX epoch.
+///
+/// Fails if `timestamp` is before the UNIX epoch, or too far in the future to be represented.
+///
+/// ```
+/// use std::time::{Duration, UNIX_EPOCH};
+/// use tomsg_rs::timestamp;
+///
+/// let timestamp = UNIX_EPOCH + Duration::from_millis(1500);
+/// assert_eq!(timestamp::to_micros(timestamp), Ok(1_500_000));
+/// assert!(timestamp::to_micros(UNIX_EPOCH - Duration::from_secs(1)).is_err());
+/// ```
+pub fn to_micros(timestamp: SystemTime) -> Result<u64, &'static str> {
+    let duration = timestamp
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| "timestamp is before the UNIX epoch")?;
+    u64::try_from(duration.as_micros()).map_err(|_| "timestamp is too far in the future")
+}
+
+/// Converts the given amount of microseconds since the UNIX epoch into a `SystemTime`.
+///
+/// Returns `None` if the result can't be represented by `SystemTime` on this platform.
+#[must_use]
+pub fn from_micros(micros: u64) -> Option<SystemTime> {
+    UNIX_EPOCH.checked_add(Duration::from_micros(micros))
+}
+
+/// Conversions between tomsg timestamps and `chrono::DateTime<Utc>`.
+///
+/// This module is only available with the `chrono` feature enabled.
+#[cfg(feature = "chrono")]
+pub mod chrono {
+    use std::convert::TryFrom;
+    use std::time::SystemTime;
+
+    use ::chrono::{DateTime, Utc};
+
+    /// Converts the given amount of microseconds since the UNIX epoch into a `DateTime<Utc>`.
+    ///
+    /// Returns `None` if the result is out of the range of `DateTime<Utc>`.
+    #[must_use]
+    pub fn from_micros(micros: u64) -> Option<DateTime<Utc>> {
+        DateTime::from_timestamp_micros(i64::try_from(micros).ok()?)
+    }
+
+    /// Converts the given `datetime` into the amount of microseconds since the UNIX epoch.
+    ///
+    /// Fails if `datetime` is before the UNIX epoch.
+    pub fn to_micros(datetime: DateTime<Utc>) -> Result<u64, &'static str> {
+        u64::try_from(datetime.timestamp_micros()).map_err(|_| "timestamp is before the UNIX epoch")
+    }
+
+    /// Converts the given `SystemTime`, such as `Message::timestamp`, into a `DateTime<Utc>`.
+    ///
+    /// Returns `None` if the result is out of the range of `DateTime<Utc>`.
+    #[must_use]
+    pub fn from_system_time(timestamp: SystemTime) -> Option<DateTime<Utc>> {
+        from_micros(super::to_micros(timestamp).ok()?)
+    }
+
+    /// Converts the given `datetime` into a `SystemTime`, such as `Command::SendAt::timestamp`.
+    ///
+    /// The `datetime` is truncated to microsecond precision, since that is the precision of
+    /// tomsg timestamps.
+    /// Fails if `datetime` is before the UNIX epoch.
+    pub fn to_system_time(datetime: DateTime<Utc>) -> Result<SystemTime, &'static str> {
+        super::from_micros(to_micros(datetime)?).ok_or("timestamp is too far in the future")
+    }
+}
+
+/// Conversions between tomsg timestamps and `time::OffsetDateTime`.
+///
+/// This module is only available with the `time` feature enabled.
+#[cfg(feature = "time")]
+pub mod time {
+    use std::convert::TryFrom;
+    use std::time::SystemTime;
+
+    use ::time::OffsetDateTime;
+
+    /// Converts the given amount of microseconds since the UNIX epoch into an `OffsetDateTime`
+    /// in UTC.
+    ///
+    /// Returns `None` if the result is out of the range of `OffsetDateTime`.
+    #[must_use]
+    pub fn from_micros(micros: u64) -> Option<OffsetDateTime> {
+        OffsetDateTime::from_unix_timestamp_nanos(i128::from(micros) * 1000).ok()
+    }
+
+    /// Converts the given `datetime` into the amount of microseconds since the UNIX epoch.
+    ///
+    /// Fails if `datetime` is before the UNIX epoch.
+    pub fn to_micros(datetime: OffsetDateTime) -> Result<u64, &'static str> {
+        u64::try_from(datetime.unix_timestamp_nanos().div_euclid(1000))
+            .map_err(|_| "timestamp is before the UNIX epoch")
+    }
+
+    /// Converts the given `SystemTime`, such as `Message::timestamp`, into an `OffsetDateTime`
+    /// in UTC.
+    ///
+    /// Returns `None` if the result is out of the range of `OffsetDateTime`.
+    #[must_use]
+    pub fn from_system_time(timestamp: SystemTime) -> Option<OffsetDateTime> {
+        from_micros(super::to_micros(timestamp).ok()?)
+    }
+
+    /// Converts the given `datetime` into a `SystemTime`, such as `Command::SendAt::timestamp`.
+    ///
+    /// The `datetime` is truncated to microsecond precision, since that is the precision of
+    /// tomsg timestamps.
+    /// Fails if `datetime` is before the UNIX epoch.
+    pub fn to_system_time(datetime: OffsetDateTime) -> Result<SystemTime, &'static str> {
+        super::from_micros(to_micros(datetime)?).ok_or("timestamp is too far in the future")
+    }
+}
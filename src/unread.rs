@@ -0,0 +1,262 @@
+//! Holds a tracker of the last read message and the unread messages in every room, which can be
+//! persisted to a file.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::id::Id;
+use crate::message::Message;
+use crate::names::{RoomName, Username};
+use crate::pushmessage::PushMessage;
+use crate::util::{invalid_data, write_atomically};
+
+/// The read state of a single room.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RoomReadState {
+    last_read: Option<Id>,
+    unread: BTreeSet<Id>,
+}
+
+impl RoomReadState {
+    /// The ID of the last message the user has read, if any.
+    #[must_use]
+    pub fn last_read(&self) -> Option<Id> {
+        self.last_read
+    }
+
+    /// The amount of unread messages.
+    #[must_use]
+    pub fn unread(&self) -> usize {
+        self.unread.len()
+    }
+
+    /// Returns an iterator over the IDs of the unread messages, oldest first.
+    pub fn unread_ids(&self) -> impl Iterator<Item = Id> + '_ {
+        self.unread.iter().copied()
+    }
+
+    fn mark_read(&mut self, id: Id) {
+        if self.last_read.is_some_and(|last_read| last_read >= id) {
+            return;
+        }
+        self.last_read = Some(id);
+        self.unread = self.unread.split_off(&id);
+        self.unread.remove(&id);
+    }
+}
+
+/// The last read message and the unread messages of every room, kept up to date by passing
+/// every received `PushMessage` to `ReadState::apply`.
+///
+/// Messages sent by the user itself are not counted as unread, and mark the room as read up to
+/// that message.
+/// The state can be persisted using `ReadState::save` and `ReadState::load`.
+///
+/// ```
+/// use std::convert::TryFrom;
+/// use tomsg_rs::unread::ReadState;
+/// use tomsg_rs::{Id, PushMessage, RoomName, Username};
+///
+/// let room = <&RoomName>::try_from("room").unwrap();
+/// let push = |id, username| {
+///     let line = format!("_push message room {} 0 {} -1 hi", username, id);
+///     PushMessage::parse(&line).unwrap().unwrap()
+/// };
+///
+/// let mut state = ReadState::new(<&Username>::try_from("me").unwrap());
+/// state.apply(&push(1, "other"));
+/// state.apply(&push(2, "other"));
+/// state.apply(&push(3, "other"));
+/// assert_eq!(state.unread(room), 3);
+///
+/// state.mark_read(room, Id::try_from(2).unwrap());
+/// assert_eq!(state.unread(room), 1);
+///
+/// // our own messages are not counted, and mark everything before them as read.
+/// state.apply(&push(4, "me"));
+/// assert_eq!(state.unread(room), 0);
+/// assert_eq!(state.last_read(room), Some(Id::try_from(4).unwrap()));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadState {
+    username: Box<Username>,
+    rooms: BTreeMap<Box<RoomName>, RoomReadState>,
+}
+
+impl ReadState {
+    /// Creates a new `ReadState` for the user with the given `username`, without any rooms.
+    #[must_use]
+    pub fn new(username: &Username) -> Self {
+        Self {
+            username: username.to_owned(),
+            rooms: BTreeMap::new(),
+        }
+    }
+
+    /// Loads the `ReadState` of the user with the given `username` from the file at `path`, as
+    /// written by `ReadState::save`.
+    ///
+    /// If the file doesn't exist, a new `ReadState` without any rooms is returned.
+    /// Fails with `io::ErrorKind::InvalidData` if the file is malformed.
+    pub fn load(path: impl AsRef<Path>, username: &Username) -> io::Result<Self> {
+        let mut state = Self::new(username);
+
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(state),
+            Err(e) => return Err(e),
+        };
+
+        let parse_id = |s: &str| -> io::Result<Id> {
+            let id: i64 = s.parse().map_err(invalid_data)?;
+            Id::try_from(id).map_err(invalid_data)
+        };
+
+        for line in contents.lines() {
+            let mut words = line.split(' ');
+            let roomname = words.next().unwrap();
+            let roomname = Box::<RoomName>::try_from(roomname.to_owned()).map_err(invalid_data)?;
+            let last_read = match words.next() {
+                None => return Err(invalid_data("missing last read message")),
+                Some("-") => None,
+                Some(id) => Some(parse_id(id)?),
+            };
+            let unread = words.map(parse_id).collect::<io::Result<_>>()?;
+
+            state
+                .rooms
+                .insert(roomname, RoomReadState { last_read, unread });
+        }
+
+        Ok(state)
+    }
+
+    /// Writes this `ReadState` to the file at `path`.
+    ///
+    /// The file is replaced atomically, so a crash while saving doesn't lose the previously
+    /// saved state.
+    ///
+    /// ```
+    /// use std::convert::TryFrom;
+    /// use tomsg_rs::unread::ReadState;
+    /// use tomsg_rs::{Id, RoomName, Username};
+    ///
+    /// let me = <&Username>::try_from("me").unwrap();
+    /// let room = <&RoomName>::try_from("room").unwrap();
+    /// let path = std::env::temp_dir().join(format!("tomsg-read-state-{}", std::process::id()));
+    ///
+    /// let mut state = ReadState::new(me);
+    /// state.mark_read(room, Id::try_from(5).unwrap());
+    /// state.save(&path).unwrap();
+    ///
+    /// assert_eq!(ReadState::load(&path, me).unwrap(), state);
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut contents = String::new();
+        for (roomname, room) in &self.rooms {
+            contents.push_str(roomname.as_str());
+            match room.last_read {
+                None => contents.push_str(" -"),
+                Some(id) => write!(contents, " {}", id).unwrap(),
+            }
+            for id in &room.unread {
+                write!(contents, " {}", id).unwrap();
+            }
+            contents.push('\n');
+        }
+
+        write_atomically(path.as_ref(), &contents)
+    }
+
+    /// Updates this `ReadState` using the given `push`.
+    ///
+    /// A `PushMessage::Message` by another user is counted as unread, unless the room is
+    /// already read up to a newer message.
+    /// A `PushMessage::Leave` of the user removes the room, other pushes are ignored.
+    pub fn apply(&mut self, push: &PushMessage) {
+        match push {
+            PushMessage::Message(message) => self.insert(message),
+            PushMessage::Leave { roomname, username } if *username == self.username => {
+                self.rooms.remove(&**roomname);
+            }
+            _ => {}
+        }
+    }
+
+    /// Adds the given `message`, such as a message retrieved using `Command::History`, counting
+    /// it as unread if it is sent by another user after the last read message.
+    pub fn insert(&mut self, message: &Message) {
+        let room = self.rooms.entry(message.roomname.clone()).or_default();
+        if message.username == self.username {
+            room.mark_read(message.id);
+        } else if room
+            .last_read
+            .is_none_or(|last_read| message.id > last_read)
+        {
+            room.unread.insert(message.id);
+        }
+    }
+
+    /// Marks the messages in the room with the given `roomname` up to and including the message
+    /// with the given `id` as read.
+    ///
+    /// Marking an older message than the last read message as read does nothing.
+    pub fn mark_read(&mut self, roomname: &RoomName, id: Id) {
+        self.rooms
+            .entry(roomname.to_owned())
+            .or_default()
+            .mark_read(id);
+    }
+
+    /// Marks every message in the room with the given `roomname` as read.
+    pub fn mark_all_read(&mut self, roomname: &RoomName) {
+        if let Some(room) = self.rooms.get_mut(roomname) {
+            if let Some(&newest) = room.unread.iter().next_back() {
+                room.mark_read(newest);
+            }
+        }
+    }
+
+    /// The username of the user this `ReadState` belongs to.
+    #[must_use]
+    pub fn username(&self) -> &Username {
+        &self.username
+    }
+
+    /// Returns the read state of the room with the given `roomname`.
+    #[must_use]
+    pub fn get(&self, roomname: &RoomName) -> Option<&RoomReadState> {
+        self.rooms.get(roomname)
+    }
+
+    /// Returns the ID of the last read message in the room with the given `roomname`.
+    #[must_use]
+    pub fn last_read(&self, roomname: &RoomName) -> Option<Id> {
+        self.get(roomname)?.last_read
+    }
+
+    /// Returns the amount of unread messages in the room with the given `roomname`.
+    #[must_use]
+    pub fn unread(&self, roomname: &RoomName) -> usize {
+        self.get(roomname).map_or(0, RoomReadState::unread)
+    }
+
+    /// Returns the amount of unread messages over all rooms.
+    #[must_use]
+    pub fn total_unread(&self) -> usize {
+        self.rooms.values().map(RoomReadState::unread).sum()
+    }
+
+    /// Returns an iterator over the names of the rooms with unread messages.
+    pub fn unread_rooms(&self) -> impl Iterator<Item = &RoomName> {
+        self.rooms
+            .iter()
+            .filter(|(_, room)| !room.unread.is_empty())
+            .map(|(roomname, _)| &**roomname)
+    }
+}
@@ -1,11 +1,115 @@
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time;
 
+use crate::id::Id;
+use crate::line::Line;
+use crate::timestamp;
 use crate::word::Word;
 
-pub fn parsei64(item: &str) -> i64 {
-    item.parse::<i64>().unwrap()
-}
-
 pub fn expect_word<S: ToString>(s: S) -> Box<Word> {
     s.to_string().try_into().unwrap()
 }
+
+/// Splits space separated fields off the front of a line, borrowing from it.
+pub struct Words<'a>(Option<&'a str>);
+
+impl<'a> Words<'a> {
+    pub fn new(s: &'a str) -> Self {
+        Self(Some(s))
+    }
+
+    fn next(&mut self, field: &str) -> Result<&'a str, String> {
+        let s = self
+            .0
+            .ok_or_else(|| format!("missing value for field: {}", field))?;
+        match s.find(' ') {
+            None => {
+                self.0 = None;
+                Ok(s)
+            }
+            Some(i) => {
+                self.0 = Some(&s[i + 1..]);
+                Ok(&s[..i])
+            }
+        }
+    }
+
+    pub fn word(&mut self, field: &str) -> Result<&'a Word, String> {
+        let word = self.next(field)?;
+        <&Word>::try_from(word).map_err(|_| format!("got invalid value for field: {}", field))
+    }
+
+    pub fn number<T: std::str::FromStr>(&mut self, field: &str) -> Result<T, String> {
+        self.next(field)?
+            .parse::<T>()
+            .map_err(|_| format!("got invalid value for field: {}", field))
+    }
+
+    pub fn id(&mut self, field: &str) -> Result<Id, String> {
+        Id::try_from(self.number::<i64>(field)?)
+            .map_err(|_| format!("got invalid value for field: {}", field))
+    }
+
+    /// Parses an optional `Id`, which is encoded as `-1` if absent.
+    pub fn optional_id(&mut self, field: &str) -> Result<Option<Id>, String> {
+        match self.number::<i64>(field)? {
+            -1 => Ok(None),
+            id => Id::try_from(id)
+                .map(Some)
+                .map_err(|_| format!("got invalid value for field: {}", field)),
+        }
+    }
+
+    /// Parses a timestamp, which is encoded as the amount of microseconds since the UNIX epoch.
+    pub fn timestamp(&mut self, field: &str) -> Result<time::SystemTime, String> {
+        let micros = self.number::<u64>(field)?;
+        timestamp::from_micros(micros)
+            .ok_or_else(|| format!("got invalid value for field: {}", field))
+    }
+
+    /// Takes the rest of the line as a single `Line`.
+    pub fn rest(&mut self, field: &str) -> Result<&'a Line, String> {
+        let rest = self
+            .0
+            .take()
+            .ok_or_else(|| format!("missing value for field: {}", field))?;
+        <&Line>::try_from(rest).map_err(|_| format!("got invalid value for field: {}", field))
+    }
+
+    /// Returns an iterator over the remaining fields.
+    pub fn remaining(self) -> impl Iterator<Item = &'a str> {
+        self.0.into_iter().flat_map(|s| s.split(' '))
+    }
+
+    /// Checks that all fields have been consumed.
+    pub fn end(self) -> Result<(), String> {
+        match self.0 {
+            None => Ok(()),
+            Some(_) => Err(String::from("got too many fields")),
+        }
+    }
+}
+
+pub fn encode_optional_id(id: Option<Id>) -> i64 {
+    match id {
+        None => -1,
+        Some(id) => id.into(),
+    }
+}
+
+pub fn invalid_data(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Replaces the file at `path` with `contents`, by writing them to a temporary file next to it
+/// which is then renamed, so the file is either replaced completely or not at all.
+pub fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    let mut tmp = OsString::from(path.as_os_str());
+    tmp.push(".tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)
+}
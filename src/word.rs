@@ -78,7 +78,7 @@ impl TryFrom<String> for Box<Word> {
     type Error = &'static str;
 
     fn try_from(val: String) -> Result<Self, Self::Error> {
-        if val.contains(|c| c == '\n' || c == ' ') {
+        if val.contains(['\n', ' ']) {
             Err("string contains newlines or spaces")
         } else {
             Ok(unsafe { Word::from_string_unchecked(val) })
@@ -90,7 +90,7 @@ impl<'a> TryFrom<&'a str> for &'a Word {
     type Error = &'static str;
 
     fn try_from(val: &'a str) -> Result<Self, Self::Error> {
-        if val.contains(|c| c == '\n' || c == ' ') {
+        if val.contains(['\n', ' ']) {
             Err("string contains newlines or spaces")
         } else {
             Ok(unsafe { Word::from_str_unchecked(val) })
@@ -125,3 +125,26 @@ impl Clone for Box<Word> {
         (**self).to_owned()
     }
 }
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Word {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Box<Word> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Self::try_from(s).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de: 'a, 'a> serde::Deserialize<'de> for &'a Word {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <&str>::deserialize(deserializer)?;
+        Self::try_from(s).map_err(serde::de::Error::custom)
+    }
+}
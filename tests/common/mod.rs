@@ -0,0 +1,45 @@
+//! Helpers shared by the integration tests that use `MockServer`.
+
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use tomsg_rs::testing::MockServer;
+use tomsg_rs::{Command, Id, Line, Message, Reply, RoomName, Username};
+
+/// The message with the given `id`, sent by "user" in "room" at `id` seconds after the epoch.
+pub fn message(id: i64) -> Message {
+    Message {
+        id: Id::try_from(id).unwrap(),
+        reply_on: None,
+        roomname: Box::<RoomName>::try_from(String::from("room")).unwrap(),
+        username: Box::<Username>::try_from(String::from("user")).unwrap(),
+        timestamp: UNIX_EPOCH + Duration::from_secs(id as u64),
+        message: Box::<Line>::try_from(format!("message {}", id)).unwrap(),
+    }
+}
+
+pub fn room() -> &'static RoomName {
+    <&RoomName>::try_from("room").unwrap()
+}
+
+/// Answers history commands with the messages with IDs `1..=newest`, in pages that overlap by
+/// `overlap` messages, where `newest` is the returned value.
+pub fn serve_history(server: &MockServer, overlap: i64) -> Arc<AtomicI64> {
+    let newest = Arc::new(AtomicI64::new(0));
+    let current = newest.clone();
+    server.set_handler(move |command| {
+        let newest = current.load(Ordering::SeqCst);
+        let (count, end) = match command {
+            Command::History { count, .. } => (*count, newest),
+            Command::HistoryBefore {
+                count, message_id, ..
+            } => (*count, i64::from(message_id) - 1 + overlap),
+            _ => return Reply::Ok,
+        };
+        let start = (end - count + 1).max(1);
+        Reply::History((start..=end).map(message).collect())
+    });
+    newest
+}
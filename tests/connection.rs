@@ -0,0 +1,788 @@
+//! Tests of `Connection` against `MockServer` and scripted servers.
+
+mod common;
+
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::common::{message, room, serve_history};
+
+use futures_util::StreamExt;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+
+use tomsg_rs::cache::MessageCache;
+use tomsg_rs::connection::{CloseReason, Direction, Error, StreamedReply, TraceEvent, Type};
+use tomsg_rs::echo::{EchoState, LocalEcho};
+use tomsg_rs::gap::GapTracker;
+use tomsg_rs::outbox::{self, Outbox, OutboxState};
+use tomsg_rs::presence::Presence;
+use tomsg_rs::state::ClientState;
+use tomsg_rs::testing::MockServer;
+use tomsg_rs::thread::Threads;
+use tomsg_rs::unread::ReadState;
+use tomsg_rs::{
+    Command, Connection, Id, Line, Message, PushMessage, Reply, RoomName, Username, Word,
+};
+
+/// Starts a server that answers the version handshake, and then every following command with
+/// the next of the given `replies`, in which `{tag}` is replaced by the tag of the command.
+/// The connection is closed after the last reply is sent.
+async fn scripted(replies: Vec<String>) -> SocketAddr {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+
+        let mut replies = replies.into_iter();
+        let mut handshake = true;
+        while let Ok(Some(line)) = lines.next_line().await {
+            let tag = line.split(' ').next().unwrap();
+            let reply = if handshake {
+                handshake = false;
+                String::from("{tag} ok\n")
+            } else {
+                match replies.next() {
+                    Some(reply) => reply,
+                    None => break,
+                }
+            };
+            let reply = reply.replace("{tag}", tag);
+            writer.write_all(reply.as_bytes()).await.unwrap();
+            if replies.len() == 0 {
+                break;
+            }
+        }
+    });
+
+    address
+}
+
+/// Encodes a history reply with the given `messages`, announcing `count` messages.
+fn history_reply(count: usize, messages: Vec<Message>) -> String {
+    let tag = <&Word>::try_from("{tag}").unwrap();
+    let reply = Reply::History(messages).encode(tag).unwrap();
+    let announced = format!("{{tag}} history {}", count);
+    let reply = reply.replacen(reply.lines().next().unwrap(), &announced, 1);
+    format!("{}\n", reply)
+}
+
+fn history_command() -> Command<'static> {
+    Command::History {
+        roomname: room().into(),
+        count: 10,
+    }
+}
+
+#[tokio::test]
+async fn streamed_history_yields_every_message() {
+    let server = MockServer::bind().await.unwrap();
+    server.enqueue(Reply::History(vec![message(1), message(2), message(3)]));
+    let (conn, _pushes) = server.connect().await.unwrap();
+
+    let command = history_command();
+    let history = conn.send_streamed(&command).await.unwrap().unwrap();
+    let history = history.history().unwrap();
+    assert_eq!(history.total(), 3);
+
+    let messages: Vec<Message> = history.collect().await;
+    assert_eq!(messages, vec![message(1), message(2), message(3)]);
+}
+
+#[tokio::test]
+async fn streamed_non_history_reply_is_returned_as_is() {
+    let server = MockServer::bind().await.unwrap();
+    let error = Box::<Line>::try_from(String::from("Not in that room")).unwrap();
+    server.enqueue(Reply::Error(error.clone()));
+    let (conn, _pushes) = server.connect().await.unwrap();
+
+    let command = history_command();
+    match conn.send_streamed(&command).await.unwrap().unwrap() {
+        StreamedReply::Reply(reply) => assert_eq!(reply, Reply::Error(error)),
+        StreamedReply::History(_) => panic!("expected a normal reply"),
+    }
+}
+
+#[tokio::test]
+async fn streamed_history_ends_early_on_close() {
+    let address = scripted(vec![history_reply(3, vec![message(1)])]).await;
+    let (conn, _pushes) = Connection::connect(Type::Plain, address).await.unwrap();
+
+    let command = history_command();
+    let history = conn.send_streamed(&command).await.unwrap().unwrap();
+    let history = history.history().unwrap();
+    assert_eq!(history.total(), 3);
+
+    let messages: Vec<Message> = history.collect().await;
+    assert_eq!(messages, vec![message(1)]);
+}
+
+async fn collect_ids(stream: impl futures_util::Stream<Item = Result<Message, Error>>) -> Vec<i64> {
+    let messages: Vec<_> = stream.collect().await;
+    messages
+        .into_iter()
+        .map(|m| i64::from(m.unwrap().id))
+        .collect()
+}
+
+#[tokio::test]
+async fn history_pages_skip_overlapping_messages() {
+    let server = MockServer::bind().await.unwrap();
+    serve_history(&server, 1).store(7, Ordering::SeqCst);
+    let (conn, _pushes) = server.connect().await.unwrap();
+
+    let ids = collect_ids(conn.history_pages(room(), 3)).await;
+    assert_eq!(ids, vec![7, 6, 5, 4, 3, 2, 1]);
+}
+
+#[tokio::test]
+async fn history_pages_end_on_empty_page() {
+    let server = MockServer::bind().await.unwrap();
+    serve_history(&server, 0).store(6, Ordering::SeqCst);
+    let (conn, _pushes) = server.connect().await.unwrap();
+
+    let ids = collect_ids(conn.history_pages(room(), 3)).await;
+    assert_eq!(ids, vec![6, 5, 4, 3, 2, 1]);
+    assert_eq!(
+        server.received(),
+        vec![
+            "version 4",
+            "history room 3",
+            "history_before room 3 4",
+            "history_before room 3 1",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn history_pages_end_after_error() {
+    let server = MockServer::bind().await.unwrap();
+    server.enqueue(Reply::History(vec![message(3), message(4)]));
+    server.enqueue(Reply::Error(
+        Box::<Line>::try_from(String::from("Not in that room")).unwrap(),
+    ));
+    let (conn, _pushes) = server.connect().await.unwrap();
+
+    let results: Vec<_> = conn.history_pages(room(), 2).collect().await;
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].as_ref().unwrap().id, Id::try_from(4).unwrap());
+    assert_eq!(results[1].as_ref().unwrap().id, Id::try_from(3).unwrap());
+    assert!(matches!(results[2], Err(Error::Server(_))));
+}
+
+fn words(reply: Vec<Box<Word>>) -> Vec<String> {
+    reply.into_iter().map(|w| w.to_string()).collect()
+}
+
+#[tokio::test]
+async fn raw_command_reply_is_unparsed() {
+    let server = MockServer::bind().await.unwrap();
+    let (conn, _pushes) = server.connect().await.unwrap();
+
+    let line = <&Line>::try_from("ping").unwrap();
+    let reply = conn.send_raw(line).await.unwrap().unwrap();
+    assert_eq!(words(reply), vec!["pong"]);
+    assert_eq!(server.received(), vec!["version 4", "ping"]);
+}
+
+#[tokio::test]
+async fn raw_command_supports_extensions() {
+    let address = scripted(vec![String::from("{tag} frobnicated 3 times\n")]).await;
+    let (conn, _pushes) = Connection::connect(Type::Plain, address).await.unwrap();
+
+    let line = <&Line>::try_from("frobnicate room").unwrap();
+    let reply = conn.send_raw(line).await.unwrap().unwrap();
+    assert_eq!(words(reply), vec!["frobnicated", "3", "times"]);
+}
+
+#[tokio::test]
+async fn invalid_reply_fails_the_command() {
+    let address = scripted(vec![
+        String::from("{tag} number many\n"),
+        String::from("{tag} pong\n"),
+    ])
+    .await;
+    let (conn, _pushes) = Connection::connect(Type::Plain, address).await.unwrap();
+
+    let reply = timeout(Duration::from_secs(5), conn.send(&Command::Ping))
+        .await
+        .expect("the command waits for a reply forever");
+    assert!(matches!(reply.unwrap(), Err(CloseReason::Err(_))));
+
+    // the connection is still usable.
+    let reply = conn.send(&Command::Ping).await.unwrap().unwrap();
+    assert_eq!(reply, Reply::Pong);
+}
+
+/// Sets a tracer on `conn` which collects the outgoing lines.
+fn trace_outgoing(conn: &Connection) -> Arc<Mutex<Vec<String>>> {
+    let traced = Arc::new(Mutex::new(Vec::new()));
+    let events = traced.clone();
+    conn.set_tracer(Some(Arc::new(move |event: &TraceEvent<'_>| {
+        if event.direction == Direction::Outgoing {
+            events.lock().unwrap().push(event.line.to_owned());
+        }
+    })));
+    traced
+}
+
+#[tokio::test]
+async fn raw_command_traces_redacted_line() {
+    let server = MockServer::bind().await.unwrap();
+    server.set_handler(|_| Reply::Ok);
+    let (conn, _pushes) = server.connect().await.unwrap();
+    let traced = trace_outgoing(&conn);
+
+    let line = <&Line>::try_from("ping secret").unwrap();
+    let redacted = <&Line>::try_from("ping <redacted>").unwrap();
+    conn.send_raw_redacted(line, redacted)
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(*traced.lock().unwrap(), vec!["ping <redacted>"]);
+    assert_eq!(server.received(), vec!["version 4", "ping secret"]);
+}
+
+#[tokio::test]
+async fn malformed_raw_credentials_are_redacted() {
+    let server = MockServer::bind().await.unwrap();
+    let (conn, _pushes) = server.connect().await.unwrap();
+    let traced = trace_outgoing(&conn);
+
+    for line in &[
+        "sendat secretkey room -1 yesterday hi",
+        "login user",
+        "change_password",
+        "login user hunter2",
+        "frobnicate room",
+    ] {
+        let line = <&Line>::try_from(*line).unwrap();
+        conn.send_raw(line).await.unwrap().unwrap();
+    }
+
+    assert_eq!(
+        *traced.lock().unwrap(),
+        vec![
+            "sendat <redacted>",
+            "login <redacted>",
+            "change_password <redacted>",
+            "login user <redacted>",
+            "frobnicate room",
+        ]
+    );
+}
+
+fn username(name: &str) -> &Username {
+    <&Username>::try_from(name).unwrap()
+}
+
+fn list(items: &[&str]) -> Reply {
+    Reply::List(
+        items
+            .iter()
+            .map(|item| Box::<Word>::try_from(item.to_string()).unwrap())
+            .collect(),
+    )
+}
+
+/// Room names with the usernames of their members.
+type Rooms = Arc<Mutex<Vec<(&'static str, Vec<&'static str>)>>>;
+
+/// Answers `list_rooms` and `list_members` using the returned rooms and their members.
+fn serve_rooms(server: &MockServer) -> Rooms {
+    let rooms: Rooms = Arc::new(Mutex::new(Vec::new()));
+    let current = rooms.clone();
+    server.set_handler(move |command| {
+        let rooms = current.lock().unwrap();
+        match command {
+            Command::ListRooms => list(&rooms.iter().map(|(r, _)| *r).collect::<Vec<_>>()),
+            Command::ListMembers { roomname } => match rooms
+                .iter()
+                .find(|(r, _)| *r == roomname.as_str())
+            {
+                Some((_, members)) => list(members),
+                None => Reply::Error(Box::<Line>::try_from(String::from("Not in room")).unwrap()),
+            },
+            _ => Reply::Ok,
+        }
+    });
+    rooms
+}
+
+#[tokio::test]
+async fn client_state_refreshes_after_reconnecting() {
+    let server = MockServer::bind().await.unwrap();
+    let rooms = serve_rooms(&server);
+    *rooms.lock().unwrap() = vec![("room", vec!["other", "user"])];
+
+    let (conn, _pushes) = server.connect().await.unwrap();
+    let mut state = ClientState::bootstrap(&conn, user()).await.unwrap();
+    assert!(state.is_member(room(), username("other")));
+
+    // the pushes sent while disconnected are missed, so the state is refreshed afterwards.
+    drop(server);
+    assert!(matches!(
+        state.refresh_room(&conn, room()).await,
+        Err(Error::Closed(_))
+    ));
+    assert!(state.is_member(room(), username("other")));
+
+    let server = MockServer::bind().await.unwrap();
+    let rooms = serve_rooms(&server);
+    *rooms.lock().unwrap() = vec![("room", vec!["user"]), ("second", vec!["third", "user"])];
+    let (conn, _pushes) = server.connect().await.unwrap();
+
+    state.refresh_room(&conn, room()).await.unwrap();
+    assert!(!state.is_member(room(), username("other")));
+    assert!(!state.contains_room(<&RoomName>::try_from("second").unwrap()));
+
+    let state = ClientState::bootstrap(&conn, user()).await.unwrap();
+    let roomnames: Vec<&str> = state.rooms().map(|r| r.as_str()).collect();
+    assert_eq!(roomnames, vec!["room", "second"]);
+    let members: Vec<&str> = state
+        .members(<&RoomName>::try_from("second").unwrap())
+        .unwrap()
+        .map(|u| u.as_str())
+        .collect();
+    assert_eq!(members, vec!["third", "user"]);
+}
+
+#[tokio::test]
+async fn presence_is_seeded_and_then_follows_pushes() {
+    let server = MockServer::bind().await.unwrap();
+    let rooms = serve_rooms(&server);
+    *rooms.lock().unwrap() = vec![("room", vec!["other", "third", "user"])];
+    let (conn, mut pushes) = server.connect().await.unwrap();
+    let state = ClientState::bootstrap(&conn, user()).await.unwrap();
+
+    server.set_handler(|command| match command {
+        Command::IsOnline { username } if username.as_str() == "third" => Reply::Number(0),
+        Command::IsOnline { .. } => Reply::Number(1),
+        _ => Reply::Ok,
+    });
+
+    let mut presence = Presence::new();
+    let changes = presence.changes();
+    presence.seed(&conn, &state).await.unwrap();
+    assert!(presence.is_online(username("other")));
+    assert!(!presence.is_online(username("third")));
+    assert_eq!(presence.get(username("third")).unwrap().last_seen, None);
+    assert_eq!(
+        server.received()[3..],
+        ["is_online other", "is_online third", "is_online user"]
+    );
+
+    server.push(&PushMessage::Online {
+        sessions: 0,
+        username: username("other").to_owned(),
+    });
+    presence.apply(&pushes.recv().await.unwrap());
+    assert!(!presence.is_online(username("other")));
+    assert!(presence.get(username("other")).unwrap().last_seen.is_some());
+
+    drop(presence);
+    let changes: Vec<(String, bool)> = changes
+        .map(|change| (change.username.to_string(), change.online))
+        .collect()
+        .await;
+    assert_eq!(
+        changes,
+        vec![
+            (String::from("other"), true),
+            (String::from("user"), true),
+            (String::from("other"), false),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn concurrent_resolves_send_one_command() {
+    let server = MockServer::bind().await.unwrap();
+    server.enqueue(Reply::Message(message(5)));
+    let (conn, _pushes) = server.connect().await.unwrap();
+
+    let cache = MessageCache::new(10);
+    let id = Id::try_from(5).unwrap();
+    let (first, second) = tokio::join!(cache.resolve(&conn, id), cache.resolve(&conn, id));
+    assert_eq!(*first.unwrap(), message(5));
+    assert_eq!(*second.unwrap(), message(5));
+
+    assert_eq!(server.received(), vec!["version 4", "get_message 5"]);
+}
+
+#[tokio::test]
+async fn concurrent_resolves_share_a_failure() {
+    let server = MockServer::bind().await.unwrap();
+    server.enqueue(Reply::Error(
+        Box::<Line>::try_from(String::from("Message not found")).unwrap(),
+    ));
+    server.enqueue(Reply::Message(message(5)));
+    let (conn, _pushes) = server.connect().await.unwrap();
+
+    let cache = MessageCache::new(10);
+    let id = Id::try_from(5).unwrap();
+    let (first, second) = tokio::join!(cache.resolve(&conn, id), cache.resolve(&conn, id));
+    let (first, second) = (first.unwrap_err(), second.unwrap_err());
+    assert!(matches!(*first, Error::Server(_)));
+    assert!(Arc::ptr_eq(&first, &second));
+    assert_eq!(server.received(), vec!["version 4", "get_message 5"]);
+
+    // a failure isn't cached, so a later call tries again.
+    assert_eq!(*cache.resolve(&conn, id).await.unwrap(), message(5));
+    assert_eq!(
+        server.received(),
+        vec!["version 4", "get_message 5", "get_message 5"]
+    );
+}
+
+fn reply(id: i64, reply_on: i64) -> Message {
+    Message {
+        reply_on: Some(Id::try_from(reply_on).unwrap()),
+        ..message(id)
+    }
+}
+
+#[tokio::test]
+async fn fetch_ancestors_skips_unavailable_messages() {
+    let server = MockServer::bind().await.unwrap();
+    server.set_handler(|command| match command {
+        Command::GetMessage(id) => match i64::from(*id) {
+            2 => Reply::Message(reply(2, 1)),
+            4 => Reply::Message(reply(4, 2)),
+            _ => Reply::Error(Box::<Line>::try_from(String::from("Message not found")).unwrap()),
+        },
+        _ => Reply::Ok,
+    });
+    let (conn, _pushes) = server.connect().await.unwrap();
+
+    let mut threads = Threads::new();
+    threads.extend(vec![reply(5, 4), reply(7, 6)]);
+    threads.fetch_ancestors(&conn).await.unwrap();
+
+    assert_eq!(threads.missing().count(), 0);
+    let mut roots: Vec<i64> = threads.roots().map(|m| i64::from(m.id)).collect();
+    roots.sort_unstable();
+    assert_eq!(roots, vec![2, 7]);
+    assert_eq!(threads.depth(Id::try_from(5).unwrap()), Some(2));
+
+    let mut requested = server.received().split_off(1);
+    requested.sort();
+    assert_eq!(
+        requested,
+        vec![
+            "get_message 1",
+            "get_message 2",
+            "get_message 4",
+            "get_message 6"
+        ]
+    );
+
+    // unavailable messages aren't requested again.
+    threads.fetch_ancestors(&conn).await.unwrap();
+    assert_eq!(server.received().len(), 5);
+}
+
+fn push_line(id: i64) -> String {
+    format!("{}\n", PushMessage::Message(message(id)).encode().unwrap())
+}
+
+#[tokio::test]
+async fn catch_up_merges_pushes_received_while_fetching() {
+    // like the reference server, the pushes are sent before the reply to the history command.
+    let address = scripted(vec![
+        push_line(7) + &push_line(9) + &history_reply(3, vec![message(6), message(7), message(8)]),
+        history_reply(3, vec![message(3), message(4), message(5)]),
+    ])
+    .await;
+    let (conn, mut pushes) = Connection::connect(Type::Plain, address).await.unwrap();
+
+    let mut tracker = GapTracker::new();
+    assert!(tracker.observe(&message(5)));
+
+    let caught_up = tracker.catch_up(&conn, &mut pushes, 3).await.unwrap();
+    let ids: Vec<i64> = caught_up
+        .into_iter()
+        .map(|push| match push {
+            PushMessage::Message(m) => i64::from(m.id),
+            p => panic!("unexpected push: {:?}", p),
+        })
+        .collect();
+    assert_eq!(ids, vec![6, 7, 8, 9]);
+    assert_eq!(tracker.newest(room()), Some(Id::try_from(9).unwrap()));
+}
+
+fn read_state_path(name: &str) -> PathBuf {
+    let name = format!("tomsg-read-state-{}-{}", std::process::id(), name);
+    std::env::temp_dir().join(name)
+}
+
+#[tokio::test]
+async fn read_state_rejects_malformed_files() {
+    let server = MockServer::bind().await.unwrap();
+    let (_conn, mut pushes) = server.connect().await.unwrap();
+
+    let mut state = ReadState::new(<&Username>::try_from("me").unwrap());
+    for id in 1..=3 {
+        server.push(&PushMessage::Message(message(id)));
+        state.apply(&pushes.recv().await.unwrap());
+    }
+    state.mark_read(room(), Id::try_from(1).unwrap());
+
+    let path = read_state_path("malformed");
+    state.save(&path).unwrap();
+    let contents = fs::read_to_string(&path).unwrap();
+    assert_eq!(contents, "room 1 2 3\n");
+    assert_eq!(ReadState::load(&path, state.username()).unwrap(), state);
+
+    // files that are cut off while writing, or corrupted otherwise.
+    for malformed in [
+        "room 1 2 ",
+        "room",
+        "room 1\nro",
+        "room x",
+        "room 1 -5",
+        "\n",
+    ] {
+        fs::write(&path, malformed).unwrap();
+        let err = ReadState::load(&path, state.username()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{:?}", malformed);
+    }
+
+    fs::remove_file(&path).unwrap();
+}
+
+fn user() -> &'static Username {
+    <&Username>::try_from("user").unwrap()
+}
+
+fn outbox_path(name: &str) -> PathBuf {
+    let name = format!("tomsg-outbox-{}-{}", std::process::id(), name);
+    std::env::temp_dir().join(name)
+}
+
+/// Returns an outbox at `path` which sent "message 7" when message 5 was the newest message in
+/// the room, but didn't receive the reply because the connection closed.
+async fn interrupted_outbox(path: &Path) -> (Outbox, u64) {
+    let _ = fs::remove_file(path);
+    let address = scripted(vec![history_reply(1, vec![message(5)])]).await;
+    let (conn, _pushes) = Connection::connect(Type::Plain, address).await.unwrap();
+
+    let mut outbox = Outbox::open(path, user()).unwrap();
+    let key = outbox.queue(room(), None, &message(7).message).unwrap();
+    assert!(outbox.flush(&conn).await.is_err());
+    assert_eq!(outbox.get(key).unwrap().state(), &OutboxState::Sent);
+
+    // the state survives restarts.
+    drop(outbox);
+    (Outbox::open(path, user()).unwrap(), key)
+}
+
+/// A message sent by the user before message 5, with the same text as message 7.
+fn sent_by_hand() -> Message {
+    Message {
+        message: message(7).message,
+        ..message(3)
+    }
+}
+
+fn send_count(server: &MockServer) -> usize {
+    let received = server.received();
+    received.iter().filter(|c| c.starts_with("send ")).count()
+}
+
+#[tokio::test]
+async fn outbox_doesnt_send_a_received_message_twice() {
+    let path = outbox_path("received");
+    let (mut outbox, key) = interrupted_outbox(&path).await;
+
+    let server = MockServer::bind().await.unwrap();
+    server.set_handler(|command| match command {
+        Command::History { .. } | Command::HistoryBefore { .. } => {
+            Reply::History(vec![sent_by_hand(), message(5), message(7), message(8)])
+        }
+        _ => Reply::Number(99),
+    });
+    let (conn, _pushes) = server.connect().await.unwrap();
+
+    outbox.flush(&conn).await.unwrap();
+    let confirmed = OutboxState::Confirmed(Id::try_from(7).unwrap());
+    assert_eq!(outbox.get(key).unwrap().state(), &confirmed);
+    assert_eq!(send_count(&server), 0);
+
+    assert_eq!(outbox.unfinished(), 0);
+    assert_eq!(Outbox::open(&path, user()).unwrap().unfinished(), 0);
+    fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn outbox_sends_a_lost_message_again() {
+    let path = outbox_path("lost");
+    let (mut outbox, key) = interrupted_outbox(&path).await;
+
+    // the message sent by hand is older than the newest message when the outbox sent its
+    // message, so it isn't mistaken for it.
+    let server = MockServer::bind().await.unwrap();
+    server.set_handler(|command| match command {
+        Command::History { count: 1, .. } => Reply::History(vec![message(6)]),
+        Command::History { .. } | Command::HistoryBefore { .. } => {
+            Reply::History(vec![sent_by_hand(), message(5), message(6)])
+        }
+        _ => Reply::Number(9),
+    });
+    let (conn, _pushes) = server.connect().await.unwrap();
+
+    outbox.flush(&conn).await.unwrap();
+    let confirmed = OutboxState::Confirmed(Id::try_from(9).unwrap());
+    assert_eq!(outbox.get(key).unwrap().state(), &confirmed);
+    assert_eq!(send_count(&server), 1);
+    fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn outbox_keeps_refused_messages() {
+    let path = outbox_path("refused");
+    let _ = fs::remove_file(&path);
+
+    let server = MockServer::bind().await.unwrap();
+    let mut next_id = 10;
+    server.set_handler(move |command| match command {
+        Command::History { .. } => Reply::History(vec![]),
+        _ => {
+            next_id += 1;
+            Reply::Number(next_id)
+        }
+    });
+    server.enqueue(Reply::History(vec![]));
+    server.enqueue(Reply::Error(
+        Box::<Line>::try_from(String::from("Not logged in")).unwrap(),
+    ));
+    let (conn, _pushes) = server.connect().await.unwrap();
+
+    let mut outbox = Outbox::open(&path, user()).unwrap();
+    let first = outbox.queue(room(), None, &message(1).message).unwrap();
+    let second = outbox.queue(room(), None, &message(2).message).unwrap();
+
+    match outbox.flush(&conn).await {
+        Err(outbox::Error::Refused { key, .. }) => assert_eq!(key, first),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    assert_eq!(outbox.get(first).unwrap().state(), &OutboxState::Pending);
+    assert_eq!(outbox.get(second).unwrap().state(), &OutboxState::Pending);
+    assert_eq!(send_count(&server), 1);
+
+    // after logging in again, the messages are sent in order.
+    outbox.flush(&conn).await.unwrap();
+    assert_eq!(outbox.unfinished(), 0);
+    let keys: Vec<u64> = outbox.take_finished().into_iter().map(|(k, _)| k).collect();
+    assert_eq!(keys, vec![first, second]);
+    assert_eq!(send_count(&server), 3);
+    fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn local_echo_matches_push_before_reply() {
+    // the reply to the send command, which has tag 1 since the version command has tag 0, is
+    // only sent after the ping, so the push is handled while the message is being sent.
+    let address = scripted(vec![push_line(7), String::from("1 number 7\n{tag} pong\n")]).await;
+    let (conn, mut pushes) = Connection::connect(Type::Plain, address).await.unwrap();
+
+    let echo = LocalEcho::new(user());
+    let key = echo.queue(room(), None, &message(7).message);
+
+    let (sent, filtered) = tokio::join!(echo.send(&conn, key), async {
+        let push = pushes.recv().await.unwrap();
+        assert_eq!(echo.get(key).unwrap().state, EchoState::Sent);
+        let filtered = echo.filter(push);
+        assert_eq!(
+            conn.send(&Command::Ping).await.unwrap().unwrap(),
+            Reply::Pong
+        );
+        filtered
+    });
+
+    let id = Id::try_from(7).unwrap();
+    assert_eq!(sent.unwrap(), id);
+    assert!(filtered.is_none());
+    assert!(echo.released().is_empty());
+    assert_eq!(echo.get(key).unwrap().state, EchoState::Confirmed(id));
+
+    // a later push of the message is dropped as well.
+    assert!(echo.filter(PushMessage::Message(message(7))).is_none());
+}
+
+#[tokio::test]
+async fn local_echo_releases_pushes_of_other_sessions() {
+    // another session of the user sends the same text, which is pushed while the message is
+    // being sent.
+    let address = scripted(vec![push_line(6), String::from("1 number 7\n{tag} pong\n")]).await;
+    let (conn, mut pushes) = Connection::connect(Type::Plain, address).await.unwrap();
+
+    let echo = LocalEcho::new(user());
+    let key = echo.queue(room(), None, &message(6).message);
+
+    let (sent, filtered) = tokio::join!(echo.send(&conn, key), async {
+        let filtered = echo.filter(pushes.recv().await.unwrap());
+        assert_eq!(
+            conn.send(&Command::Ping).await.unwrap().unwrap(),
+            Reply::Pong
+        );
+        filtered
+    });
+
+    assert_eq!(sent.unwrap(), Id::try_from(7).unwrap());
+    assert!(filtered.is_none());
+    assert_eq!(echo.released(), vec![PushMessage::Message(message(6))]);
+    assert!(echo.released().is_empty());
+}
+
+#[tokio::test]
+async fn local_echo_marks_refused_messages_as_failed() {
+    let server = MockServer::bind().await.unwrap();
+    server.enqueue(Reply::Error(
+        Box::<Line>::try_from(String::from("Not in that room")).unwrap(),
+    ));
+    server.enqueue(Reply::Number(3));
+    let (conn, _pushes) = server.connect().await.unwrap();
+
+    let echo = LocalEcho::new(user());
+    let key = echo.queue(room(), None, &message(3).message);
+
+    // a push of another user with the same text isn't mistaken for the message.
+    let push = PushMessage::Message(Message {
+        username: Box::<Username>::try_from(String::from("other")).unwrap(),
+        message: message(3).message,
+        ..message(2)
+    });
+    assert!(echo.filter(push).is_some());
+
+    assert!(matches!(echo.send(&conn, key).await, Err(Error::Server(_))));
+    assert_eq!(echo.get(key).unwrap().state, EchoState::Failed);
+
+    let id = echo.send(&conn, key).await.unwrap();
+    assert_eq!(id, Id::try_from(3).unwrap());
+    assert_eq!(echo.get(key).unwrap().state, EchoState::Confirmed(id));
+}
+
+#[tokio::test]
+#[should_panic(expected = "local message already sent")]
+async fn local_echo_doesnt_send_confirmed_messages_again() {
+    let server = MockServer::bind().await.unwrap();
+    server.enqueue(Reply::Number(3));
+    let (conn, _pushes) = server.connect().await.unwrap();
+
+    let echo = LocalEcho::new(user());
+    let key = echo.queue(room(), None, &message(3).message);
+    echo.send(&conn, key).await.unwrap();
+    let _ = echo.send(&conn, key).await;
+}
@@ -0,0 +1,395 @@
+//! Property-based tests of the encoding and decoding of the tomsg wire format.
+
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::BytesMut;
+use proptest::prelude::*;
+use tokio_util::codec::{Decoder, Encoder};
+
+use tomsg_rs::codec::{ClientCodec, ClientFrame, ServerCodec};
+use tomsg_rs::{
+    ApiKey, Command, Id, Line, Message, PushMessage, PushMessageRef, Reply, RoomName, Word,
+};
+
+fn word() -> impl Strategy<Value = Box<Word>> {
+    "[^ \n]{0,12}".prop_map(|s| Box::<Word>::try_from(s).unwrap())
+}
+
+/// A `Word` converted into one of the typed names, such as `RoomName`.
+fn name<T>() -> impl Strategy<Value = Box<T>>
+where
+    T: ?Sized + std::fmt::Debug,
+    Box<T>: From<Box<Word>>,
+{
+    word().prop_map(Box::<T>::from)
+}
+
+fn tag() -> impl Strategy<Value = Box<Word>> {
+    // a tag of "_push" can't be distinguished from a push message.
+    word().prop_filter("tag can't be _push", |w| w.as_str() != "_push")
+}
+
+fn line() -> impl Strategy<Value = Box<Line>> {
+    "[^\n]{0,40}".prop_map(|s| Box::<Line>::try_from(s).unwrap())
+}
+
+fn id() -> impl Strategy<Value = Id> {
+    (0..=i64::MAX).prop_map(|i| Id::try_from(i).unwrap())
+}
+
+fn timestamp() -> impl Strategy<Value = SystemTime> {
+    // timestamps are sent with microsecond precision.
+    (0..u64::from(u32::MAX) * 1_000_000)
+        .prop_map(|micros| UNIX_EPOCH + Duration::from_micros(micros))
+}
+
+fn message() -> impl Strategy<Value = Message> {
+    (
+        id(),
+        proptest::option::of(id()),
+        name(),
+        name(),
+        timestamp(),
+        line(),
+    )
+        .prop_map(
+            |(id, reply_on, roomname, username, timestamp, message)| Message {
+                id,
+                reply_on,
+                roomname,
+                username,
+                timestamp,
+                message,
+            },
+        )
+}
+
+fn command() -> impl Strategy<Value = Command<'static>> {
+    fn w<T>() -> impl Strategy<Value = Cow<'static, T>>
+    where
+        T: ?Sized + std::fmt::Debug + ToOwned<Owned = Box<T>> + 'static,
+        Box<T>: From<Box<Word>>,
+    {
+        name().prop_map(Cow::Owned)
+    }
+    let l = || line().prop_map(Cow::Owned);
+
+    prop_oneof![
+        w().prop_map(Command::Version),
+        (w(), l()).prop_map(|(username, password)| Command::Register { username, password }),
+        (w(), l()).prop_map(|(username, password)| Command::Login { username, password }),
+        l().prop_map(Command::ChangePassword),
+        Just(Command::Logout),
+        Just(Command::ListRooms),
+        w().prop_map(|roomname| Command::ListMembers { roomname }),
+        Just(Command::CreateRoom),
+        w().prop_map(Command::LeaveRoom),
+        (w(), w()).prop_map(|(roomname, username)| Command::Invite { roomname, username }),
+        (w(), proptest::option::of(id()), l()).prop_map(|(roomname, reply_on, message)| {
+            Command::Send {
+                roomname,
+                reply_on,
+                message,
+            }
+        }),
+        (w(), w(), proptest::option::of(id()), timestamp(), l()).prop_map(
+            |(apikey, roomname, reply_on, timestamp, message)| Command::SendAt {
+                apikey,
+                roomname,
+                reply_on,
+                timestamp,
+                message,
+            }
+        ),
+        (w(), any::<i64>()).prop_map(|(roomname, count)| Command::History { roomname, count }),
+        (w(), any::<i64>(), id()).prop_map(|(roomname, count, message_id)| {
+            Command::HistoryBefore {
+                roomname,
+                count,
+                message_id,
+            }
+        }),
+        id().prop_map(Command::GetMessage),
+        Just(Command::Ping),
+        w().prop_map(|username| Command::IsOnline { username }),
+        w().prop_map(Command::FirebaseToken),
+        w().prop_map(Command::DeleteFirebaseToken),
+        any::<i64>().prop_map(Command::UserActive),
+    ]
+}
+
+fn reply() -> impl Strategy<Value = Reply> {
+    prop_oneof![
+        Just(Reply::Ok),
+        any::<i64>().prop_map(Reply::Number),
+        line().prop_map(Reply::Error),
+        name().prop_map(Reply::Name),
+        proptest::collection::vec(name(), 0..5).prop_map(Reply::List),
+        Just(Reply::Pong),
+        proptest::collection::vec(message(), 0..5).prop_map(Reply::History),
+        message().prop_map(Reply::Message),
+    ]
+}
+
+fn push_message() -> impl Strategy<Value = PushMessage> {
+    prop_oneof![
+        (any::<i64>(), name())
+            .prop_map(|(sessions, username)| PushMessage::Online { sessions, username }),
+        message().prop_map(PushMessage::Message),
+        (name(), name()).prop_map(|(roomname, inviter)| PushMessage::Invite { roomname, inviter }),
+        (name(), name()).prop_map(|(roomname, username)| PushMessage::Join { roomname, username }),
+        (name(), name()).prop_map(|(roomname, username)| PushMessage::Leave { roomname, username }),
+    ]
+}
+
+/// Decodes every frame in `buf` using `codec`, panicking on errors.
+fn decode_all<D: Decoder>(codec: &mut D, buf: &mut BytesMut) -> Vec<D::Item>
+where
+    D::Error: std::fmt::Debug,
+{
+    let mut res = vec![];
+    while let Some(frame) = codec.decode(buf).unwrap() {
+        res.push(frame);
+    }
+    assert!(buf.is_empty());
+    res
+}
+
+/// Reassembles the frames of a single reply, returning its tag and the `Reply`.
+fn reassemble(frames: Vec<ClientFrame>) -> (Box<Word>, Reply) {
+    let mut frames = frames.into_iter();
+    match frames.next().unwrap() {
+        ClientFrame::Reply { tag, reply } => {
+            assert!(frames.next().is_none());
+            (tag, reply)
+        }
+        ClientFrame::History { tag, count } => {
+            let mut items = vec![];
+            for (i, frame) in frames.enumerate() {
+                match frame {
+                    ClientFrame::HistoryMessage {
+                        tag: t,
+                        index,
+                        message,
+                    } => {
+                        assert_eq!(t, tag);
+                        assert_eq!(index, i as i64);
+                        items.push(message);
+                    }
+                    f => panic!("unexpected frame: {:?}", f),
+                }
+            }
+            assert_eq!(count, items.len() as i64);
+            (tag, Reply::History(items))
+        }
+        f => panic!("unexpected frame: {:?}", f),
+    }
+}
+
+proptest! {
+    #[test]
+    fn command_roundtrip(command in command()) {
+        let encoded = command.encode().unwrap();
+        let parsed = Command::parse(&encoded).unwrap();
+        prop_assert_eq!(&parsed.encode().unwrap(), &encoded);
+        prop_assert_eq!(parsed, command);
+    }
+
+    #[test]
+    fn command_codec_roundtrip(tag in tag(), command in command()) {
+        let mut buf = BytesMut::new();
+        ClientCodec::new().encode((&*tag, &command), &mut buf).unwrap();
+
+        let frames = decode_all(&mut ServerCodec::new(), &mut buf);
+        prop_assert_eq!(frames.len(), 1);
+        prop_assert_eq!(&frames[0].tag, &tag);
+        prop_assert_eq!(frames[0].command.as_ref().unwrap(), &command);
+    }
+
+    #[test]
+    fn reply_roundtrip(tag in tag(), reply in reply()) {
+        let mut buf = BytesMut::new();
+        ServerCodec::new().encode((&*tag, &reply), &mut buf).unwrap();
+        let encoded = buf.clone();
+
+        let (t, r) = reassemble(decode_all(&mut ClientCodec::new(), &mut buf));
+        prop_assert_eq!(&t, &tag);
+        prop_assert_eq!(&r, &reply);
+
+        ServerCodec::new().encode((&*t, &r), &mut buf).unwrap();
+        prop_assert_eq!(buf, encoded);
+    }
+
+    #[test]
+    fn push_message_roundtrip(push in push_message()) {
+        let mut buf = BytesMut::new();
+        ServerCodec::new().encode(&push, &mut buf).unwrap();
+
+        let frames = decode_all(&mut ClientCodec::new(), &mut buf);
+        prop_assert_eq!(frames.len(), 1);
+        match &frames[0] {
+            ClientFrame::Push(p) => {
+                prop_assert_eq!(p, &push);
+                prop_assert_eq!(p.encode(), push.encode());
+            }
+            f => panic!("unexpected frame: {:?}", f),
+        }
+    }
+
+    #[test]
+    fn push_message_ref_roundtrip(push in push_message()) {
+        let line = push.encode().unwrap();
+        let borrowed = PushMessageRef::parse(&line).unwrap().unwrap();
+        prop_assert_eq!(borrowed.into_owned(), push);
+    }
+
+    #[test]
+    fn client_decode_doesnt_panic(input in proptest::collection::vec(any::<u8>(), 0..200)) {
+        let mut codec = ClientCodec::new();
+        let mut buf = BytesMut::from(&input[..]);
+        while let Ok(Some(_)) = codec.decode(&mut buf) {}
+    }
+
+    #[test]
+    fn client_decode_lines_doesnt_panic(input in "(_push |[0-9] )?[a-z_]{0,16}( [-0-9a-z]{0,4}){0,10}\n") {
+        let mut codec = ClientCodec::new();
+        let mut buf = BytesMut::from(input.as_bytes());
+        while let Ok(Some(_)) = codec.decode(&mut buf) {}
+    }
+
+    #[test]
+    fn server_decode_doesnt_panic(input in proptest::collection::vec(any::<u8>(), 0..200)) {
+        let mut codec = ServerCodec::new();
+        let mut buf = BytesMut::from(&input[..]);
+        while let Ok(Some(_)) = codec.decode(&mut buf) {}
+    }
+
+    #[test]
+    fn command_parse_doesnt_panic(input in "[a-z_]{0,22}( [-0-9a-z]{0,4}){0,6}") {
+        let _ = Command::parse(&input);
+    }
+}
+
+#[cfg(feature = "serde")]
+proptest! {
+    #[test]
+    fn command_serde_roundtrip(command in command()) {
+        let json = serde_json::to_string(&command).unwrap();
+        prop_assert_eq!(serde_json::from_str::<Command>(&json).unwrap(), command);
+    }
+
+    #[test]
+    fn reply_serde_roundtrip(reply in reply()) {
+        let json = serde_json::to_string(&reply).unwrap();
+        prop_assert_eq!(serde_json::from_str::<Reply>(&json).unwrap(), reply);
+    }
+
+    #[test]
+    fn push_message_serde_roundtrip(push in push_message()) {
+        let json = serde_json::to_string(&push).unwrap();
+        prop_assert_eq!(serde_json::from_str::<PushMessage>(&json).unwrap(), push);
+    }
+
+    #[test]
+    fn message_ref_serializes_like_message(message in message()) {
+        let json = serde_json::to_string(&message).unwrap();
+        prop_assert_eq!(serde_json::to_string(&tomsg_rs::MessageRef::from(&message)).unwrap(), json);
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_validates() {
+    assert!(serde_json::from_str::<Box<Word>>("\"a word\"").is_err());
+    assert!(serde_json::from_str::<&Word>("\"word\"").is_ok());
+    assert!(serde_json::from_str::<Box<Line>>("\"a\\nline\"").is_err());
+    assert!(serde_json::from_str::<Box<Line>>("\"a line\"").is_ok());
+    assert!(serde_json::from_str::<Id>("-1").is_err());
+    assert_eq!(
+        serde_json::from_str::<Id>("1").unwrap(),
+        Id::try_from(1).unwrap()
+    );
+
+    let message = r#"{"id":1,"reply_on":null,"roomname":"room","username":"a user",
+        "timestamp":{"secs_since_epoch":0,"nanos_since_epoch":0},"message":"hi"}"#;
+    assert!(serde_json::from_str::<Message>(message).is_err());
+}
+
+#[test]
+fn pre_epoch_timestamp_fails_to_encode() {
+    let message = Box::<Line>::try_from(String::from("hi")).unwrap();
+    let command = Command::SendAt {
+        apikey: <&ApiKey>::try_from("key").unwrap().into(),
+        roomname: <&RoomName>::try_from("room").unwrap().into(),
+        reply_on: None,
+        timestamp: UNIX_EPOCH - Duration::from_secs(1),
+        message: message.into(),
+    };
+    assert!(command.encode().is_err());
+
+    let tag = <&Word>::try_from("0").unwrap();
+    let err = ClientCodec::new()
+        .encode((tag, &command), &mut BytesMut::new())
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[cfg(feature = "chrono")]
+proptest! {
+    #[test]
+    fn chrono_roundtrip(timestamp in timestamp()) {
+        let datetime = tomsg_rs::timestamp::chrono::from_system_time(timestamp).unwrap();
+        let back = tomsg_rs::timestamp::chrono::to_system_time(datetime).unwrap();
+        prop_assert_eq!(back, timestamp);
+    }
+}
+
+#[cfg(feature = "time")]
+proptest! {
+    #[test]
+    fn time_roundtrip(timestamp in timestamp()) {
+        let datetime = tomsg_rs::timestamp::time::from_system_time(timestamp).unwrap();
+        let back = tomsg_rs::timestamp::time::to_system_time(datetime).unwrap();
+        prop_assert_eq!(back, timestamp);
+    }
+}
+
+#[test]
+fn codec_rejects_long_lines_and_recovers() {
+    let mut codec = ServerCodec::new_with_max_length(8);
+    assert_eq!(codec.max_length(), 8);
+
+    let mut buf = BytesMut::from(&b"1 ping\n2 too_long_command\n3 ping\n"[..]);
+    let frame = codec.decode(&mut buf).unwrap().unwrap();
+    assert_eq!(frame.tag.as_str(), "1");
+
+    let err = codec.decode(&mut buf).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+    let frame = codec.decode(&mut buf).unwrap().unwrap();
+    assert_eq!(frame.tag.as_str(), "3");
+    assert_eq!(frame.command.unwrap(), Command::Ping);
+    assert!(buf.is_empty());
+}
+
+#[test]
+fn codec_rejects_long_partial_lines() {
+    let mut codec = ClientCodec::new_with_max_length(6);
+    let mut buf = BytesMut::from(&b"1 pong"[..]);
+    assert!(codec.decode(&mut buf).unwrap().is_none());
+
+    buf.extend_from_slice(b"pong");
+    assert!(codec.decode(&mut buf).is_err());
+
+    // the rest of the long line is skipped once it arrives.
+    buf.extend_from_slice(b"pong\n2 pong\n");
+    match codec.decode(&mut buf).unwrap().unwrap() {
+        ClientFrame::Reply { tag, reply } => {
+            assert_eq!(tag.as_str(), "2");
+            assert_eq!(reply, Reply::Pong);
+        }
+        f => panic!("unexpected frame: {:?}", f),
+    }
+}
@@ -0,0 +1,204 @@
+//! Tests of `Connection` against the tomsg-server binary.
+
+use std::convert::TryFrom;
+use std::io::{BufRead, BufReader};
+use std::net::SocketAddr;
+use std::process::{Child, Command as Process, Stdio};
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+
+use tomsg_rs::connection::Type;
+use tomsg_rs::{Command, Connection, Id, Line, PushMessage, Reply, RoomName, Username, Word};
+
+/// A running tomsg-server, which is killed when dropped.
+struct Server {
+    child: Child,
+    address: SocketAddr,
+}
+
+impl Server {
+    fn start() -> Self {
+        let mut child = Process::new(env!("CARGO_BIN_EXE_tomsg-server"))
+            .arg("127.0.0.1:0")
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let mut line = String::new();
+        let stderr = child.stderr.take().unwrap();
+        BufReader::new(stderr).read_line(&mut line).unwrap();
+        let address = line
+            .trim_end()
+            .strip_prefix("listening on ")
+            .unwrap_or_else(|| panic!("unexpected output: {:?}", line))
+            .parse()
+            .unwrap();
+
+        Self { child, address }
+    }
+
+    async fn connect(&self) -> (Connection, mpsc::Receiver<PushMessage>) {
+        Connection::connect(Type::Plain, self.address)
+            .await
+            .unwrap()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn username(s: &str) -> &Username {
+    <&Username>::try_from(s).unwrap()
+}
+
+fn line(s: &str) -> &Line {
+    <&Line>::try_from(s).unwrap()
+}
+
+async fn request(conn: &Connection, command: &Command<'_>) -> Reply {
+    conn.send(command).await.unwrap().unwrap()
+}
+
+/// Registers and logs in a user with the given `name`.
+async fn login(conn: &Connection, name: &str) {
+    let username = username(name);
+    let password = line("hunter2");
+
+    let register = Command::Register {
+        username: username.into(),
+        password: password.into(),
+    };
+    assert_eq!(request(conn, &register).await, Reply::Ok);
+    let login = Command::Login {
+        username: username.into(),
+        password: password.into(),
+    };
+    assert_eq!(request(conn, &login).await, Reply::Ok);
+}
+
+async fn next_message(pushes: &mut mpsc::Receiver<PushMessage>) -> PushMessage {
+    loop {
+        let push = timeout(Duration::from_secs(5), pushes.recv())
+            .await
+            .expect("no push message arrived")
+            .unwrap();
+        if let PushMessage::Message(_) = push {
+            return push;
+        }
+    }
+}
+
+#[tokio::test]
+async fn register_login_send_and_history() {
+    let server = Server::start();
+    let (alice, _alice_pushes) = server.connect().await;
+    let (bob, mut bob_pushes) = server.connect().await;
+    login(&alice, "alice").await;
+    login(&bob, "bob").await;
+
+    let roomname: Box<RoomName> = match request(&alice, &Command::CreateRoom).await {
+        Reply::Name(name) => name.into(),
+        r => panic!("unexpected reply: {:?}", r),
+    };
+    let invite = Command::Invite {
+        roomname: (&*roomname).into(),
+        username: username("bob").into(),
+    };
+    assert_eq!(request(&alice, &invite).await, Reply::Ok);
+
+    let mut ids = Vec::new();
+    for text in &["first", "second", "third"] {
+        let send = Command::Send {
+            roomname: (&*roomname).into(),
+            reply_on: ids.last().copied(),
+            message: line(text).into(),
+        };
+        match request(&alice, &send).await {
+            Reply::Number(id) => ids.push(Id::try_from(id).unwrap()),
+            r => panic!("unexpected reply: {:?}", r),
+        }
+    }
+
+    // bob receives every message as a push, in order.
+    for (id, text) in ids.iter().zip(&["first", "second", "third"]) {
+        match next_message(&mut bob_pushes).await {
+            PushMessage::Message(m) => {
+                assert_eq!(m.id, *id);
+                assert_eq!(*m.username, *username("alice"));
+                assert_eq!(*m.message, *line(text));
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    let history = Command::History {
+        roomname: (&*roomname).into(),
+        count: 10,
+    };
+    match request(&bob, &history).await {
+        Reply::History(messages) => {
+            let got: Vec<_> = messages.iter().map(|m| m.id).collect();
+            assert_eq!(got, ids);
+            assert_eq!(messages[0].reply_on, None);
+            assert_eq!(messages[2].reply_on, Some(ids[1]));
+        }
+        r => panic!("unexpected reply: {:?}", r),
+    }
+
+    let pages: Vec<_> = bob.history_pages(&roomname, 2).collect().await;
+    let got: Vec<_> = pages.into_iter().map(|m| m.unwrap().id).collect();
+    let mut expected = ids.clone();
+    expected.reverse();
+    assert_eq!(got, expected);
+}
+
+#[tokio::test]
+async fn commands_need_a_login() {
+    let server = Server::start();
+    let (conn, _pushes) = server.connect().await;
+
+    match request(&conn, &Command::CreateRoom).await {
+        Reply::Error(e) => assert_eq!(*e, *line("Not logged in")),
+        r => panic!("unexpected reply: {:?}", r),
+    }
+
+    login(&conn, "carol").await;
+    let login = Command::Login {
+        username: username("carol").into(),
+        password: line("hunter2").into(),
+    };
+    match request(&conn, &login).await {
+        Reply::Error(e) => assert_eq!(*e, *line("Already logged in")),
+        r => panic!("unexpected reply: {:?}", r),
+    }
+
+    let version = <&Word>::try_from("4").unwrap();
+    assert_eq!(
+        request(&conn, &Command::Version(version.into())).await,
+        Reply::Ok
+    );
+}
+
+#[tokio::test]
+async fn long_lines_disconnect_the_client() {
+    let server = Server::start();
+    let (conn, _pushes) = server.connect().await;
+
+    let long = Box::<Line>::try_from(format!("ping {}", "x".repeat(100_000))).unwrap();
+    let reply = timeout(Duration::from_secs(5), conn.send_raw(&long))
+        .await
+        .expect("the server keeps buffering the line");
+    assert!(!matches!(reply, Ok(Ok(_))));
+    assert!(conn.is_closed().await);
+
+    // the server still accepts other clients.
+    let (conn, _pushes) = server.connect().await;
+    assert_eq!(request(&conn, &Command::Ping).await, Reply::Pong);
+}
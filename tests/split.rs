@@ -0,0 +1,125 @@
+//! Property-based tests of splitting text into messages and reassembling it.
+
+use std::convert::TryFrom;
+use std::time::UNIX_EPOCH;
+
+use proptest::prelude::*;
+
+use tomsg_rs::split::{reassemble, split_text, Continuation};
+use tomsg_rs::{Id, Line, Message, RoomName, Username};
+
+/// Text, possibly with blank lines and leading or trailing whitespace, without words that don't
+/// fit in a part of 8 bytes.
+fn text() -> impl Strategy<Value = String> {
+    let line = "[ \t\r]{0,2}([a-zé]{1,3}([ \t\r]{1,3}[a-zé]{1,3}){0,12})?[ \t\r]{0,2}";
+    proptest::collection::vec(line, 1..6).prop_map(|lines| lines.join("\n"))
+}
+
+fn messages(parts: Vec<Box<Line>>, continuation: &Continuation) -> Vec<Message> {
+    let count = parts.len();
+    parts
+        .into_iter()
+        .enumerate()
+        .map(|(i, part)| {
+            let (reply_on, message) = match continuation {
+                Continuation::ReplyChain if i > 0 => (Some(i as i64 - 1), part),
+                Continuation::Marker(marker) if i + 1 < count => (
+                    None,
+                    Box::<Line>::try_from(format!("{}{}", part, marker)).unwrap(),
+                ),
+                _ => (None, part),
+            };
+            Message {
+                id: Id::try_from(i as i64).unwrap(),
+                reply_on: reply_on.map(|id| Id::try_from(id).unwrap()),
+                roomname: Box::<RoomName>::try_from(String::from("room")).unwrap(),
+                username: Box::<Username>::try_from(String::from("user")).unwrap(),
+                timestamp: UNIX_EPOCH,
+                message,
+            }
+        })
+        .collect()
+}
+
+fn roundtrip(text: &str, max_length: usize, continuation: &Continuation) -> Vec<String> {
+    let parts = split_text(text, max_length);
+    for part in &parts {
+        assert!(part.len() <= max_length);
+    }
+
+    let messages = messages(parts, continuation);
+    reassemble(&messages, continuation)
+        .into_iter()
+        .map(|r| r.text)
+        .collect()
+}
+
+proptest! {
+    #[test]
+    fn reply_chain_roundtrip(text in text(), max_length in 8usize..40) {
+        let reassembled = roundtrip(&text, max_length, &Continuation::ReplyChain);
+        prop_assert_eq!(reassembled, vec![text]);
+    }
+
+    #[test]
+    fn marker_roundtrip(text in text(), max_length in 8usize..40) {
+        let marker = Continuation::Marker(Box::<Line>::try_from(String::from("…")).unwrap());
+        let reassembled = roundtrip(&text, max_length, &marker);
+        prop_assert_eq!(reassembled, vec![text]);
+    }
+
+    #[test]
+    fn split_text_doesnt_panic(text in "\\PC{0,100}", max_length in 4usize..20) {
+        let _ = split_text(&text, max_length);
+    }
+}
+
+fn message(id: i64, username: &str, reply_on: Option<i64>, text: &str) -> Message {
+    Message {
+        id: Id::try_from(id).unwrap(),
+        reply_on: reply_on.map(|id| Id::try_from(id).unwrap()),
+        roomname: Box::<RoomName>::try_from(String::from("room")).unwrap(),
+        username: Box::<Username>::try_from(String::from(username)).unwrap(),
+        timestamp: UNIX_EPOCH,
+        message: Box::<Line>::try_from(String::from(text)).unwrap(),
+    }
+}
+
+#[test]
+fn paragraphs_roundtrip() {
+    let text = "para one\n\npara two\n";
+    let parts = split_text(text, 7);
+    let parts: Vec<_> = parts.iter().map(|l| l.as_str()).collect();
+    assert_eq!(parts, vec!["para ", "one", "", "para ", "two", ""]);
+
+    let reassembled = roundtrip(text, 7, &Continuation::ReplyChain);
+    assert_eq!(reassembled, vec![text]);
+}
+
+#[test]
+fn trailing_whitespace_roundtrips() {
+    let text = "ends with space \nnext";
+    let reassembled = roundtrip(text, 1024, &Continuation::ReplyChain);
+    assert_eq!(reassembled, vec![text]);
+}
+
+#[test]
+fn replies_of_others_dont_close_a_split_message() {
+    let messages = vec![
+        message(1, "a", None, "hello "),
+        message(2, "b", Some(1), "nice"),
+        message(3, "a", Some(1), "world"),
+    ];
+    let texts: Vec<_> = reassemble(&messages, &Continuation::ReplyChain)
+        .into_iter()
+        .map(|r| r.text)
+        .collect();
+    assert_eq!(texts, vec!["hello world", "nice"]);
+}
+
+#[test]
+fn long_words_are_joined_by_a_newline() {
+    let text = "abcdefghij klm";
+    let reassembled = roundtrip(text, 8, &Continuation::ReplyChain);
+    assert_eq!(reassembled, vec!["abcdefgh\nij klm"]);
+}
@@ -0,0 +1,237 @@
+//! Tests of `MessageStore` synchronizing with `MockServer`, and searching it.
+
+mod common;
+
+use std::convert::TryFrom;
+use std::sync::atomic::Ordering;
+#[cfg(feature = "search")]
+use std::time::{Duration, UNIX_EPOCH};
+
+use tomsg_rs::store::MessageStore;
+#[cfg(feature = "search")]
+use tomsg_rs::store::SearchQuery;
+use tomsg_rs::testing::MockServer;
+use tomsg_rs::{Id, PushMessage};
+#[cfg(feature = "search")]
+use tomsg_rs::{Line, Message, RoomName, Username};
+
+use crate::common::{message, room, serve_history};
+
+fn id(id: i64) -> Id {
+    Id::try_from(id).unwrap()
+}
+
+fn stored_ids(store: &MessageStore) -> Vec<i64> {
+    store
+        .range(room(), .., 100)
+        .unwrap()
+        .into_iter()
+        .map(|m| i64::from(m.id))
+        .collect()
+}
+
+#[tokio::test]
+async fn sync_fills_gap_before_applied_push() {
+    let server = MockServer::bind().await.unwrap();
+    let newest = serve_history(&server, 0);
+    let (conn, _pushes) = server.connect().await.unwrap();
+    let store = MessageStore::open_in_memory().unwrap();
+
+    newest.store(5, Ordering::SeqCst);
+    assert_eq!(store.sync(&conn, room(), 3).await.unwrap(), 3);
+    assert_eq!(store.synced(room()).unwrap(), Some(id(5)));
+
+    // while disconnected messages 6 to 9 are missed, after which message 10 is pushed.
+    newest.store(10, Ordering::SeqCst);
+    store.apply(&PushMessage::Message(message(10))).unwrap();
+    assert_eq!(store.latest(room()).unwrap(), Some(id(10)));
+    assert_eq!(store.synced(room()).unwrap(), Some(id(5)));
+
+    assert_eq!(store.sync(&conn, room(), 3).await.unwrap(), 4);
+    assert_eq!(stored_ids(&store), (3..=10).collect::<Vec<_>>());
+    assert_eq!(store.synced(room()).unwrap(), Some(id(10)));
+}
+
+#[tokio::test]
+async fn backfill_from_empty_room_marks_newest_as_synced() {
+    let server = MockServer::bind().await.unwrap();
+    let newest = serve_history(&server, 0);
+    let (conn, _pushes) = server.connect().await.unwrap();
+    let store = MessageStore::open_in_memory().unwrap();
+
+    newest.store(8, Ordering::SeqCst);
+    assert_eq!(store.backfill(&conn, room(), 3, 5).await.unwrap(), 5);
+    assert_eq!(stored_ids(&store), (4..=8).collect::<Vec<_>>());
+    assert_eq!(store.synced(room()).unwrap(), Some(id(8)));
+
+    newest.store(9, Ordering::SeqCst);
+    assert_eq!(store.sync(&conn, room(), 3).await.unwrap(), 1);
+    assert_eq!(store.backfill(&conn, room(), 3, 10).await.unwrap(), 3);
+    assert_eq!(stored_ids(&store), (1..=9).collect::<Vec<_>>());
+}
+
+#[cfg(feature = "search")]
+fn search_ids(store: &MessageStore, query: &SearchQuery) -> Vec<i64> {
+    let mut ids: Vec<i64> = store
+        .search(query)
+        .unwrap()
+        .into_iter()
+        .map(|r| i64::from(r.id))
+        .collect();
+    ids.sort_unstable();
+    ids
+}
+
+#[cfg(feature = "search")]
+#[test]
+fn search_filters_exclude_other_messages() {
+    let store = MessageStore::open_in_memory().unwrap();
+    store
+        .insert_all(&[
+            message(1),
+            Message {
+                roomname: Box::<RoomName>::try_from(String::from("other")).unwrap(),
+                ..message(2)
+            },
+            Message {
+                username: Box::<Username>::try_from(String::from("other")).unwrap(),
+                ..message(3)
+            },
+            message(4),
+            message(5),
+        ])
+        .unwrap();
+
+    let query = SearchQuery::new("message");
+    assert_eq!(search_ids(&store, &query), vec![1, 2, 3, 4, 5]);
+
+    let in_room = SearchQuery {
+        roomname: Some(room().to_owned()),
+        ..query.clone()
+    };
+    assert_eq!(search_ids(&store, &in_room), vec![1, 3, 4, 5]);
+
+    let by_user = SearchQuery {
+        username: Some(Box::<Username>::try_from(String::from("user")).unwrap()),
+        ..query.clone()
+    };
+    assert_eq!(search_ids(&store, &by_user), vec![1, 2, 4, 5]);
+
+    // messages are sent `id` seconds after the epoch, `to` is exclusive.
+    let in_range = SearchQuery {
+        from: Some(UNIX_EPOCH + Duration::from_secs(2)),
+        to: Some(UNIX_EPOCH + Duration::from_secs(5)),
+        ..query.clone()
+    };
+    assert_eq!(search_ids(&store, &in_range), vec![2, 3, 4]);
+
+    let combined = SearchQuery {
+        roomname: Some(room().to_owned()),
+        username: Some(Box::<Username>::try_from(String::from("user")).unwrap()),
+        from: Some(UNIX_EPOCH + Duration::from_secs(2)),
+        ..query.clone()
+    };
+    assert_eq!(search_ids(&store, &combined), vec![4, 5]);
+
+    let limited = SearchQuery { limit: 2, ..query };
+    assert_eq!(store.search(&limited).unwrap().len(), 2);
+}
+
+#[cfg(feature = "search")]
+#[test]
+fn search_highlights_matching_words() {
+    let store = MessageStore::open_in_memory().unwrap();
+    store
+        .insert(&Message {
+            message: Box::<Line>::try_from(String::from("Fox and fox, no foxes")).unwrap(),
+            ..message(1)
+        })
+        .unwrap();
+
+    let query = SearchQuery {
+        highlight_start: String::from("<b>"),
+        highlight_end: String::from("</b>"),
+        ..SearchQuery::new("FOX")
+    };
+    let results = store.search(&query).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].snippet, "<b>Fox</b> and <b>fox</b>, no foxes");
+}
+
+#[cfg(feature = "search")]
+#[tokio::test]
+async fn search_indexes_messages_stored_before_the_index() {
+    let server = MockServer::bind().await.unwrap();
+    let newest = serve_history(&server, 0);
+    let (conn, _pushes) = server.connect().await.unwrap();
+
+    let path = std::env::temp_dir().join(format!("tomsg-store-{}-index", std::process::id()));
+    newest.store(3, Ordering::SeqCst);
+    let store = MessageStore::open(&path).unwrap();
+    store.sync(&conn, room(), 10).await.unwrap();
+    drop(store);
+
+    // make the database look like it was created without the search feature.
+    let db = rusqlite::Connection::open(&path).unwrap();
+    db.execute_batch("DROP TRIGGER messages_fts_insert; DROP TABLE messages_fts;")
+        .unwrap();
+    drop(db);
+
+    let store = MessageStore::open(&path).unwrap();
+    assert_eq!(
+        search_ids(&store, &SearchQuery::new("message")),
+        vec![1, 2, 3]
+    );
+
+    // messages stored afterwards are indexed as well.
+    newest.store(4, Ordering::SeqCst);
+    store.sync(&conn, room(), 10).await.unwrap();
+    assert_eq!(search_ids(&store, &SearchQuery::new("4")), vec![4]);
+
+    drop(store);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "search")]
+#[test]
+fn search_matches_fts_syntax_literally() {
+    let store = MessageStore::open_in_memory().unwrap();
+    let text = |id, text: &str| Message {
+        message: Box::<Line>::try_from(text.to_owned()).unwrap(),
+        ..message(id)
+    };
+    store
+        .insert_all(&[
+            text(1, "apples OR pears"),
+            text(2, "apples"),
+            text(3, "pears"),
+            text(4, "say \"hi\" NOT bye"),
+        ])
+        .unwrap();
+
+    // operators are words to match, not operators.
+    assert_eq!(
+        search_ids(&store, &SearchQuery::new("apples OR pears")),
+        vec![1]
+    );
+    assert_eq!(search_ids(&store, &SearchQuery::new("NOT bye")), vec![4]);
+    assert_eq!(search_ids(&store, &SearchQuery::new("\"hi\"")), vec![4]);
+
+    // unbalanced quotes and other syntax don't make the query fail.
+    for query in [
+        "\"",
+        "pears\"",
+        "(apples",
+        "message:apples",
+        "apples*",
+        "^",
+        "NEAR(a b)",
+    ] {
+        assert!(store.search(&SearchQuery::new(query)).is_ok(), "{}", query);
+    }
+    assert_eq!(
+        search_ids(&store, &SearchQuery::new("message:apples")),
+        vec![]
+    );
+    assert!(search_ids(&store, &SearchQuery::new("\" \"")).is_empty());
+}